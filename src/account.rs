@@ -0,0 +1,172 @@
+//! Accounts as stored in the vault
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{AuthError, Result};
+use crate::hotp::HOTP;
+use crate::totp::{Algorithm, OtpauthUri, TOTP};
+
+/// Issuer used in provisioning URIs when an account does not record one
+pub const DEFAULT_ISSUER: &str = "CLI Authenticator";
+
+/// Code length of an account added without choosing one
+pub const DEFAULT_DIGITS: u32 = 6;
+/// Seconds each TOTP code is valid for when an account does not choose
+pub const DEFAULT_PERIOD: u64 = 30;
+
+/// Whether an account produces time-based or counter-based codes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    #[default]
+    Totp,
+    Hotp,
+}
+
+impl fmt::Display for OtpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtpKind::Totp => f.write_str("totp"),
+            OtpKind::Hotp => f.write_str("hotp"),
+        }
+    }
+}
+
+impl FromStr for OtpKind {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "totp" => Ok(OtpKind::Totp),
+            "hotp" => Ok(OtpKind::Hotp),
+            _ => Err(AuthError::InvalidParameter(format!(
+                "unknown account type '{}', expected totp or hotp",
+                s
+            ))),
+        }
+    }
+}
+
+/// Code generation parameters chosen when an account is added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountParams {
+    pub kind: OtpKind,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    pub counter: u64,
+}
+
+impl Default for AccountParams {
+    fn default() -> Self {
+        Self {
+            kind: OtpKind::default(),
+            algorithm: Algorithm::default(),
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            counter: 0,
+        }
+    }
+}
+
+/// A stored account record: its secret, code generation parameters and metadata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    #[serde(rename = "type")]
+    pub kind: OtpKind,
+    pub secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    /// Next HOTP counter value; unused for TOTP accounts
+    #[serde(default)]
+    pub counter: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Unix timestamp of when the account was added, unknown for migrated accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Time step of the last code accepted by `verify --record`; codes from this step
+    /// or earlier are rejected as replays
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_verified_step: Option<u64>,
+}
+
+impl Account {
+    /// Creates an account added now; `secret` is the Base32 shared secret
+    pub fn new(secret: String, params: AccountParams) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+
+        Self {
+            kind: params.kind,
+            secret,
+            issuer: None,
+            algorithm: params.algorithm,
+            digits: params.digits,
+            period: params.period,
+            counter: params.counter,
+            tags: Vec::new(),
+            notes: None,
+            created_at,
+            last_verified_step: None,
+        }
+    }
+
+    /// Builds an account from a parsed `otpauth://` URI
+    pub fn from_uri(uri: &OtpauthUri) -> Self {
+        let params = AccountParams {
+            kind: uri.kind,
+            algorithm: uri.algorithm,
+            digits: uri.digits,
+            period: uri.period,
+            counter: uri.counter,
+        };
+        Self {
+            issuer: uri.issuer.clone(),
+            ..Self::new(uri.secret.clone(), params)
+        }
+    }
+
+    /// Code generator of a TOTP account
+    pub fn totp(&self) -> Result<TOTP> {
+        TOTP::new(&self.secret)?
+            .with_algorithm(self.algorithm)
+            .with_digits(self.digits)?
+            .with_period(self.period)
+    }
+
+    /// Code generator of an HOTP account
+    pub fn hotp(&self) -> Result<HOTP> {
+        HOTP::new(&self.secret)?
+            .with_algorithm(self.algorithm)
+            .with_digits(self.digits)
+    }
+
+    /// Checks that codes can be generated from the secret and parameters
+    pub fn validate(&self) -> Result<()> {
+        match self.kind {
+            OtpKind::Totp => self.totp()?.now()?,
+            OtpKind::Hotp => self.hotp()?.generate(self.counter)?,
+        };
+        Ok(())
+    }
+
+    /// otpauth URI for enrolling the account in another app, for example as a QR code
+    pub fn provisioning_uri(&self, name: &str) -> Result<String> {
+        let issuer = self.issuer.as_deref().unwrap_or(DEFAULT_ISSUER);
+        match self.kind {
+            OtpKind::Totp => Ok(self.totp()?.provisioning_uri(name, issuer)),
+            OtpKind::Hotp => Ok(self.hotp()?.provisioning_uri(name, issuer, self.counter)),
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Self-contained archives of a vault, encrypted with a passphrase of their own rather
+//! than the vault's key so that they can be restored on another machine or after a reset
+
+use age::{Decryptor, Encryptor};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account::Account;
+use crate::authenticator::OnConflict;
+use crate::error::{AuthError, Result};
+use crate::recipient::Recipient;
+
+/// Identifies r-auth archives among other passphrase-encrypted age files
+const FORMAT: &str = "r-auth-backup";
+
+/// Version of the archive layout written by [`Backup::seal`]
+const VERSION: u32 = 1;
+
+/// The vault an archive was made from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultInfo {
+    /// Name of a named vault, `None` for the default vault or one given by path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Whether the vault had a key of its own rather than the shared one
+    #[serde(default)]
+    pub own_key: bool,
+    /// The user's key file the vault was encrypted to, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+    /// Public keys the vault was shared with, as listed by `r-auth recipient`, except
+    /// its own key: the vault it is restored to adds its own instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+}
+
+/// Accounts and settings of a vault at the time it was backed up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backup {
+    /// Unix timestamp of when the archive was made
+    pub created_at: u64,
+    pub vault: VaultInfo,
+    pub accounts: HashMap<String, Account>,
+}
+
+/// Contents of an archive once decrypted
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    backup: Backup,
+}
+
+/// How [`TOTPAuthenticator::restore`](crate::authenticator::TOTPAuthenticator::restore)
+/// combines an archive with the vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreMode {
+    /// Keeps the vault's accounts and adds those of the archive, settling accounts in
+    /// both as [`OnConflict`] says
+    Merge(OnConflict),
+    /// Makes the vault hold exactly the accounts of the archive
+    Replace,
+}
+
+/// How the accounts of an archive compare with those of a vault, each list sorted
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    /// Only in the archive
+    pub added: Vec<String>,
+    /// In both, with different secrets or settings
+    pub changed: Vec<String>,
+    /// In both and identical
+    pub unchanged: Vec<String>,
+    /// Only in the vault
+    pub missing: Vec<String>,
+}
+
+/// A setting that differs between an archive and a vault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change<T> {
+    pub backup: T,
+    pub vault: T,
+}
+
+/// How the key setup recorded in an archive differs from that of the vault it is
+/// restored to. Restoring never changes the vault's key, so these are only reported,
+/// for the user to set up again with `init` or `vault create`
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct KeySetupDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub own_key: Option<Change<bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<Change<Option<PathBuf>>>,
+}
+
+impl<T: Clone + PartialEq> Change<T> {
+    /// The change from `vault` to `backup`, `None` if they are the same
+    fn between(backup: &T, vault: &T) -> Option<Self> {
+        (backup != vault).then(|| Self {
+            backup: backup.clone(),
+            vault: vault.clone(),
+        })
+    }
+}
+
+impl KeySetupDiff {
+    pub fn is_empty(&self) -> bool {
+        self.own_key.is_none() && self.key_file.is_none()
+    }
+}
+
+impl Backup {
+    /// Captures `accounts` of the vault described by `vault` as of now
+    pub fn new(vault: VaultInfo, accounts: HashMap<String, Account>) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            created_at,
+            vault,
+            accounts,
+        }
+    }
+
+    /// Encrypts the archive to `passphrase` with age's scrypt recipient
+    pub fn seal(&self, passphrase: SecretString) -> Result<Vec<u8>> {
+        let contents = serde_json::to_vec_pretty(&Archive {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            backup: self.clone(),
+        })?;
+
+        let mut encrypted = vec![];
+        let mut writer = Encryptor::with_user_passphrase(passphrase)
+            .wrap_output(&mut encrypted)
+            .map_err(|e| AuthError::Encryption(e.to_string()))?;
+        writer.write_all(&contents)?;
+        writer.finish()?;
+        Ok(encrypted)
+    }
+
+    /// Decrypts an archive made by [`Backup::seal`] and checks every account in it, so
+    /// that a damaged or foreign file is rejected before anything is restored
+    pub fn open(data: &[u8], passphrase: SecretString) -> Result<Self> {
+        let invalid = |reason: String| AuthError::InvalidBackup(reason);
+
+        let decryptor = Decryptor::new(data)
+            .map_err(|_| invalid("not an r-auth backup: the file is not age-encrypted".into()))?;
+        if !decryptor.is_scrypt() {
+            return Err(invalid(
+                "not an r-auth backup: the file is not encrypted with a passphrase".into(),
+            ));
+        }
+
+        let identity = age::scrypt::Identity::new(passphrase);
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .map_err(|e| AuthError::Passphrase(format!("Failed to unlock the backup: {}", e)))?;
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents)?;
+
+        let header: serde_json::Value = serde_json::from_slice(&contents)
+            .map_err(|e| invalid(format!("not an r-auth backup: {}", e)))?;
+        if header.get("format").and_then(|format| format.as_str()) != Some(FORMAT) {
+            return Err(invalid("not an r-auth backup".into()));
+        }
+        match header.get("version").and_then(|version| version.as_u64()) {
+            Some(version) if version == u64::from(VERSION) => {}
+            Some(version) => {
+                return Err(invalid(format!(
+                    "backup version {} was made by a newer r-auth",
+                    version
+                )))
+            }
+            None => return Err(invalid("the backup has no version".into())),
+        }
+
+        let archive: Archive =
+            serde_json::from_value(header).map_err(|e| invalid(e.to_string()))?;
+        let backup = archive.backup;
+        for (name, account) in &backup.accounts {
+            if name.trim().is_empty() {
+                return Err(invalid("an account has an empty name".into()));
+            }
+            account
+                .validate()
+                .map_err(|e| invalid(format!("account '{}': {}", name, e)))?;
+        }
+        backup.recipients()?;
+        Ok(backup)
+    }
+
+    /// The public keys the vault was shared with
+    pub fn recipients(&self) -> Result<Vec<Recipient>> {
+        self.vault
+            .recipients
+            .iter()
+            .map(|recipient| recipient.parse())
+            .collect::<Result<_>>()
+            .map_err(|e| AuthError::InvalidBackup(e.to_string()))
+    }
+
+    /// Compares the key setup recorded in the archive with that of `vault`
+    pub fn key_setup_diff(&self, vault: &VaultInfo) -> KeySetupDiff {
+        KeySetupDiff {
+            own_key: Change::between(&self.vault.own_key, &vault.own_key),
+            key_file: Change::between(&self.vault.key_file, &vault.key_file),
+        }
+    }
+
+    /// Compares the archive with the accounts of a vault
+    pub fn diff(&self, accounts: &HashMap<String, Account>) -> Diff {
+        let mut diff = Diff::default();
+        for (name, account) in &self.accounts {
+            match accounts.get(name) {
+                None => diff.added.push(name.clone()),
+                Some(current) if current == account => diff.unchanged.push(name.clone()),
+                Some(_) => diff.changed.push(name.clone()),
+            }
+        }
+        diff.missing = accounts
+            .keys()
+            .filter(|name| !self.accounts.contains_key(*name))
+            .cloned()
+            .collect();
+
+        for names in [
+            &mut diff.added,
+            &mut diff.changed,
+            &mut diff.unchanged,
+            &mut diff.missing,
+        ] {
+            names.sort();
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountParams;
+
+    fn passphrase(passphrase: &str) -> SecretString {
+        SecretString::from(passphrase)
+    }
+
+    fn account(secret: &str) -> Account {
+        Account::new(secret.to_string(), AccountParams::default())
+    }
+
+    #[test]
+    fn sealed_backup_opens_with_its_passphrase_only() {
+        let backup = Backup::new(
+            VaultInfo {
+                name: Some("work".into()),
+                ..VaultInfo::default()
+            },
+            HashMap::from([("GitHub".to_string(), account("JBSWY3DPEHPK3PXP"))]),
+        );
+        let sealed = backup.seal(passphrase("correct horse")).unwrap();
+        assert!(!sealed.windows(16).any(|w| w == b"JBSWY3DPEHPK3PXP"));
+
+        assert_eq!(
+            Backup::open(&sealed, passphrase("correct horse")).unwrap(),
+            backup
+        );
+        assert!(matches!(
+            Backup::open(&sealed, passphrase("battery staple")),
+            Err(AuthError::Passphrase(_))
+        ));
+        assert!(matches!(
+            Backup::open(b"{\"accounts\": {}}", passphrase("correct horse")),
+            Err(AuthError::InvalidBackup(_))
+        ));
+    }
+
+    #[test]
+    fn key_setup_round_trips_and_is_compared() {
+        let info = VaultInfo {
+            name: Some("work".into()),
+            own_key: true,
+            key_file: Some(PathBuf::from("/home/me/.ssh/id_ed25519")),
+            recipients: Vec::new(),
+        };
+        let sealed = Backup::new(info.clone(), HashMap::new())
+            .seal(passphrase("correct horse"))
+            .unwrap();
+        let backup = Backup::open(&sealed, passphrase("correct horse")).unwrap();
+        assert_eq!(backup.vault, info);
+        assert!(backup.key_setup_diff(&info).is_empty());
+
+        let diff = backup.key_setup_diff(&VaultInfo::default());
+        assert_eq!(
+            diff.own_key,
+            Some(Change {
+                backup: true,
+                vault: false
+            })
+        );
+        assert_eq!(
+            diff.key_file,
+            Some(Change {
+                backup: info.key_file,
+                vault: None
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_archives_with_invalid_accounts() {
+        let backup = Backup::new(
+            VaultInfo::default(),
+            HashMap::from([("Broken".to_string(), account("not base32!"))]),
+        );
+        let sealed = backup.seal(passphrase("correct horse")).unwrap();
+        let error = Backup::open(&sealed, passphrase("correct horse")).unwrap_err();
+        assert!(error.to_string().contains("'Broken'"), "{}", error);
+    }
+
+    #[test]
+    fn diff_sorts_accounts_by_outcome() {
+        let backup = Backup::new(
+            VaultInfo::default(),
+            HashMap::from([
+                ("AWS".to_string(), account("JBSWY3DPEHPK3PXP")),
+                ("GitHub".to_string(), account("JBSWY3DPEHPK3PXP")),
+                ("GitLab".to_string(), account("JBSWY3DPEHPK3PXP")),
+            ]),
+        );
+        let mut current = backup.accounts.clone();
+        current.remove("GitLab");
+        current.get_mut("GitHub").unwrap().secret = "GEZDGNBVGY3TQOJQ".into();
+        current.insert("VPN".into(), account("GEZDGNBVGY3TQOJQ"));
+
+        assert_eq!(
+            backup.diff(&current),
+            Diff {
+                added: vec!["GitLab".into()],
+                changed: vec!["GitHub".into()],
+                unchanged: vec!["AWS".into()],
+                missing: vec!["VPN".into()],
+            }
+        );
+    }
+}
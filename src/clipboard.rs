@@ -0,0 +1,355 @@
+//! Copying codes to the system clipboard or over OSC 52, and clearing them afterwards
+
+use base64::Engine;
+use std::env;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use r_auth::error::{AuthError, Result};
+
+/// Seconds a copied code stays on the clipboard by default
+pub const DEFAULT_CLEAR_AFTER: u64 = 20;
+
+/// A system clipboard
+pub trait Clipboard {
+    fn set(&mut self, text: &str) -> Result<()>;
+
+    /// Current contents, or `None` if this clipboard cannot be read back
+    fn get(&mut self) -> Result<Option<String>>;
+
+    fn clear(&mut self) -> Result<()>;
+}
+
+/// Which clipboard to use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClipboardBackend {
+    /// The first backend usable in the current session
+    #[default]
+    Auto,
+    /// wl-copy and wl-paste
+    Wayland,
+    Xclip,
+    Xsel,
+    /// pbcopy and pbpaste on macOS
+    Pbcopy,
+    /// OSC 52 escape sequence, copied by the terminal even over SSH
+    Osc52,
+}
+
+impl FromStr for ClipboardBackend {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ClipboardBackend::Auto),
+            "wayland" => Ok(ClipboardBackend::Wayland),
+            "xclip" => Ok(ClipboardBackend::Xclip),
+            "xsel" => Ok(ClipboardBackend::Xsel),
+            "pbcopy" => Ok(ClipboardBackend::Pbcopy),
+            "osc52" => Ok(ClipboardBackend::Osc52),
+            _ => Err(AuthError::InvalidParameter(format!(
+                "unknown clipboard '{}', expected auto, wayland, xclip, xsel, pbcopy or osc52",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ClipboardBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardBackend::Auto => f.write_str("auto"),
+            ClipboardBackend::Wayland => f.write_str("wayland"),
+            ClipboardBackend::Xclip => f.write_str("xclip"),
+            ClipboardBackend::Xsel => f.write_str("xsel"),
+            ClipboardBackend::Pbcopy => f.write_str("pbcopy"),
+            ClipboardBackend::Osc52 => f.write_str("osc52"),
+        }
+    }
+}
+
+impl ClipboardBackend {
+    /// Resolves `Auto` to a concrete backend
+    /// Over SSH the local clipboard is out of reach, so OSC 52 is used there and
+    /// whenever no clipboard tool is installed
+    pub fn detect(self) -> Self {
+        if self != ClipboardBackend::Auto {
+            return self;
+        }
+
+        let ssh = env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some();
+        let session = |var| !ssh && env::var_os(var).is_some();
+
+        if cfg!(target_os = "macos") && !ssh && on_path("pbcopy") {
+            ClipboardBackend::Pbcopy
+        } else if session("WAYLAND_DISPLAY") && on_path("wl-copy") {
+            ClipboardBackend::Wayland
+        } else if session("DISPLAY") && on_path("xclip") {
+            ClipboardBackend::Xclip
+        } else if session("DISPLAY") && on_path("xsel") {
+            ClipboardBackend::Xsel
+        } else {
+            ClipboardBackend::Osc52
+        }
+    }
+
+    pub fn open(self) -> Box<dyn Clipboard> {
+        match self.detect() {
+            ClipboardBackend::Wayland => Box::new(CommandClipboard {
+                copy: &["wl-copy"],
+                paste: &["wl-paste", "--no-newline"],
+                clear: Some(&["wl-copy", "--clear"]),
+            }),
+            ClipboardBackend::Xclip => Box::new(CommandClipboard {
+                copy: &["xclip", "-selection", "clipboard"],
+                paste: &["xclip", "-selection", "clipboard", "-o"],
+                clear: None,
+            }),
+            ClipboardBackend::Xsel => Box::new(CommandClipboard {
+                copy: &["xsel", "--clipboard", "--input"],
+                paste: &["xsel", "--clipboard", "--output"],
+                clear: Some(&["xsel", "--clipboard", "--clear"]),
+            }),
+            ClipboardBackend::Pbcopy => Box::new(CommandClipboard {
+                copy: &["pbcopy"],
+                paste: &["pbpaste"],
+                clear: None,
+            }),
+            ClipboardBackend::Osc52 | ClipboardBackend::Auto => {
+                // stderr rather than stdout so piped output stays clean
+                Box::new(Osc52::new(std::io::stderr()))
+            }
+        }
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| is_file(&dir.join(program))))
+        .unwrap_or(false)
+}
+
+fn is_file(path: &Path) -> bool {
+    path.metadata().map(|m| m.is_file()).unwrap_or(false)
+}
+
+/// A clipboard driven by external copy and paste commands
+pub struct CommandClipboard {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+    /// Command that empties the clipboard; copying an empty string otherwise
+    clear: Option<&'static [&'static str]>,
+}
+
+impl CommandClipboard {
+    fn command(args: &[&str]) -> Command {
+        let mut command = Command::new(args[0]);
+        // The copy tools keep running to serve the selection, so they must not
+        // hold on to our stdout
+        command
+            .args(&args[1..])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        command
+    }
+
+    fn run(args: &[&str], input: &str) -> Result<()> {
+        let mut child = Self::command(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| AuthError::Clipboard(format!("Failed to run {}: {}", args[0], e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(AuthError::Clipboard(format!(
+                "{} exited with {}",
+                args[0], status
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Clipboard for CommandClipboard {
+    fn set(&mut self, text: &str) -> Result<()> {
+        Self::run(self.copy, text)
+    }
+
+    fn get(&mut self) -> Result<Option<String>> {
+        let output = Self::command(self.paste)
+            .stdout(Stdio::piped())
+            .output()
+            .map_err(|e| AuthError::Clipboard(format!("Failed to run {}: {}", self.paste[0], e)))?;
+        // Paste tools fail when the clipboard is empty
+        if !output.status.success() {
+            return Ok(Some(String::new()));
+        }
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        match self.clear {
+            Some(args) => Self::run(args, ""),
+            None => Self::run(self.copy, ""),
+        }
+    }
+}
+
+/// Sets the clipboard of the terminal `out` is attached to with an OSC 52 escape
+/// sequence. Terminals do not let the clipboard be read back this way
+pub struct Osc52<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Osc52<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    fn write(&mut self, payload: &str) -> Result<()> {
+        write!(self.out, "\x1b]52;c;{}\x07", payload)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Clipboard for Osc52<W> {
+    fn set(&mut self, text: &str) -> Result<()> {
+        self.write(&base64::engine::general_purpose::STANDARD.encode(text))
+    }
+
+    fn get(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.write("")
+    }
+}
+
+/// Clears the clipboard if it still holds `text`, so anything copied since is kept
+/// Clipboards that cannot be read back are left alone. Returns whether it was cleared
+pub fn clear_if_unchanged(clipboard: &mut dyn Clipboard, text: &str) -> Result<bool> {
+    match clipboard.get()? {
+        Some(current) if current == text => {
+            clipboard.clear()?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Clears `text` from the clipboard after `after` in a detached `r-auth` process,
+/// so the current command can exit immediately. The text is passed on stdin to keep
+/// it out of the process list
+pub fn clear_later(backend: ClipboardBackend, text: &str, after: Duration) -> Result<()> {
+    let backend = backend.detect();
+    if backend == ClipboardBackend::Osc52 {
+        // Nothing to compare against, see clear_if_unchanged
+        return Ok(());
+    }
+
+    let mut child = Command::new(env::current_exe()?)
+        .args([
+            "clear-clipboard",
+            "--after",
+            &after.as_secs().to_string(),
+            "--clipboard",
+            &backend.to_string(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| AuthError::Clipboard(format!("Failed to schedule clearing: {}", e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Counterpart of [`clear_later`] run in the detached process
+pub fn wait_and_clear(backend: ClipboardBackend, after: Duration) -> Result<()> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+
+    thread::sleep(after);
+    clear_if_unchanged(backend.open().as_mut(), &text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory clipboard standing in for the system one
+    #[derive(Default)]
+    struct Memory(Option<String>);
+
+    impl Clipboard for Memory {
+        fn set(&mut self, text: &str) -> Result<()> {
+            self.0 = Some(text.to_string());
+            Ok(())
+        }
+
+        fn get(&mut self) -> Result<Option<String>> {
+            Ok(Some(self.0.clone().unwrap_or_default()))
+        }
+
+        fn clear(&mut self) -> Result<()> {
+            self.0 = None;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn osc52_emits_base64_escape_sequence() {
+        let mut out = Vec::new();
+        Osc52::new(&mut out).set("123456").unwrap();
+        assert_eq!(out, b"\x1b]52;c;MTIzNDU2\x07");
+
+        let mut out = Vec::new();
+        Osc52::new(&mut out).clear().unwrap();
+        assert_eq!(out, b"\x1b]52;c;\x07");
+    }
+
+    #[test]
+    fn clears_only_our_code() {
+        let mut clipboard = Memory::default();
+        clipboard.set("123456").unwrap();
+        assert!(clear_if_unchanged(&mut clipboard, "123456").unwrap());
+        assert_eq!(clipboard.0, None);
+
+        clipboard.set("something else").unwrap();
+        assert!(!clear_if_unchanged(&mut clipboard, "123456").unwrap());
+        assert_eq!(clipboard.0.as_deref(), Some("something else"));
+    }
+
+    #[test]
+    fn unreadable_clipboard_is_left_alone() {
+        let mut out = Vec::new();
+        assert!(!clear_if_unchanged(&mut Osc52::new(&mut out), "123456").unwrap());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn parses_backend_names() {
+        assert_eq!(
+            "OSC52".parse::<ClipboardBackend>().unwrap(),
+            ClipboardBackend::Osc52
+        );
+        assert!("clipboard".parse::<ClipboardBackend>().is_err());
+        assert_eq!(
+            ClipboardBackend::Xsel.detect(),
+            ClipboardBackend::Xsel,
+            "explicit backends are kept"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Settings read from `config.toml` in the r-auth config directory, overridden by
+//! `R_AUTH_*` environment variables and then by command-line flags
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::clipboard::DEFAULT_CLEAR_AFTER;
+use r_auth::account::{DEFAULT_DIGITS, DEFAULT_ISSUER, DEFAULT_PERIOD};
+use r_auth::error::{AuthError, Result};
+use r_auth::totp::Algorithm;
+
+/// Name of the configuration file in the r-auth config directory
+const CONFIG_FILE: &str = "config.toml";
+
+/// A configurable setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// Issuer recorded on accounts added with `add`
+    Issuer,
+    Algorithm,
+    Digits,
+    Period,
+    /// Whether `add` prints the provisioning QR code
+    ShowQr,
+    /// Whether to ask before replacing or removing an account
+    Confirm,
+    /// Seconds before a copied code is cleared from the clipboard
+    ClearAfter,
+}
+
+impl Key {
+    pub const ALL: [Key; 7] = [
+        Key::Issuer,
+        Key::Algorithm,
+        Key::Digits,
+        Key::Period,
+        Key::ShowQr,
+        Key::Confirm,
+        Key::ClearAfter,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::Issuer => "issuer",
+            Key::Algorithm => "algorithm",
+            Key::Digits => "digits",
+            Key::Period => "period",
+            Key::ShowQr => "show_qr",
+            Key::Confirm => "confirm",
+            Key::ClearAfter => "clear_after",
+        }
+    }
+
+    /// Environment variable overriding the setting, such as `R_AUTH_DIGITS`
+    pub fn env_var(self) -> String {
+        format!("R_AUTH_{}", self.name().to_ascii_uppercase())
+    }
+}
+
+impl FromStr for Key {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.to_ascii_lowercase().replace('-', "_");
+        Key::ALL
+            .into_iter()
+            .find(|key| key.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Key::ALL.iter().map(|key| key.name()).collect();
+                AuthError::InvalidParameter(format!(
+                    "unknown setting '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where an effective value came from, from weakest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File => f.write_str("file"),
+            Source::Env => f.write_str("env"),
+            Source::Cli => f.write_str("cli"),
+        }
+    }
+}
+
+/// Effective value of every setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub issuer: String,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    pub show_qr: bool,
+    pub confirm: bool,
+    pub clear_after: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            issuer: DEFAULT_ISSUER.to_string(),
+            algorithm: Algorithm::default(),
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            show_qr: true,
+            confirm: true,
+            clear_after: DEFAULT_CLEAR_AFTER,
+        }
+    }
+}
+
+impl Settings {
+    /// Parses and validates `value` as the new value of `key`
+    fn set(&mut self, key: Key, value: &str) -> Result<()> {
+        let invalid = |expected: &str| {
+            AuthError::InvalidParameter(format!(
+                "invalid {} '{}', expected {}",
+                key, value, expected
+            ))
+        };
+
+        match key {
+            Key::Issuer => {
+                if value.trim().is_empty() {
+                    return Err(invalid("a non-empty name"));
+                }
+                self.issuer = value.to_string();
+            }
+            Key::Algorithm => self.algorithm = value.parse()?,
+            Key::Digits => {
+                self.digits = value
+                    .parse()
+                    .ok()
+                    .filter(|digits| (6..=8).contains(digits))
+                    .ok_or_else(|| invalid("a number between 6 and 8"))?;
+            }
+            Key::Period => {
+                self.period = value
+                    .parse()
+                    .ok()
+                    .filter(|&period| period > 0)
+                    .ok_or_else(|| invalid("a number of seconds greater than zero"))?;
+            }
+            Key::ShowQr => self.show_qr = parse_bool(value).ok_or_else(|| invalid("a boolean"))?,
+            Key::Confirm => self.confirm = parse_bool(value).ok_or_else(|| invalid("a boolean"))?,
+            Key::ClearAfter => {
+                self.clear_after = value.parse().map_err(|_| invalid("a number of seconds"))?
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of `key` as written to the config file
+    fn toml_value(&self, key: Key) -> toml::Value {
+        match key {
+            Key::Issuer => self.issuer.clone().into(),
+            Key::Algorithm => self.algorithm.to_string().into(),
+            Key::Digits => i64::from(self.digits).into(),
+            Key::Period => (self.period as i64).into(),
+            Key::ShowQr => self.show_qr.into(),
+            Key::Confirm => self.confirm.into(),
+            Key::ClearAfter => (self.clear_after as i64).into(),
+        }
+    }
+
+    /// The value of `key` as shown to the user
+    pub fn get(&self, key: Key) -> String {
+        match key {
+            Key::Issuer => self.issuer.clone(),
+            Key::Algorithm => self.algorithm.to_string(),
+            Key::Digits => self.digits.to_string(),
+            Key::Period => self.period.to_string(),
+            Key::ShowQr => self.show_qr.to_string(),
+            Key::Confirm => self.confirm.to_string(),
+            Key::ClearAfter => self.clear_after.to_string(),
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Settings after layering the config file, the environment and `--set` flags
+#[derive(Debug, Default)]
+pub struct Config {
+    pub settings: Settings,
+    sources: HashMap<Key, Source>,
+}
+
+impl Config {
+    /// Location of the config file in the r-auth config directory
+    pub fn path() -> Result<PathBuf> {
+        Ok(r_auth::vault::config_root()?.join(CONFIG_FILE))
+    }
+
+    /// Reads the config file at `path`, then applies the variables returned by `env`
+    /// and finally `overrides` given on the command line as `KEY=VALUE`
+    pub fn load(
+        path: &Path,
+        env: impl Fn(&str) -> Option<String>,
+        overrides: &[String],
+    ) -> Result<Self> {
+        let mut config = Self::default();
+
+        for (name, value) in read_file(path)? {
+            let value = match value {
+                toml::Value::String(value) => value,
+                value => value.to_string(),
+            };
+            name.parse()
+                .and_then(|key| config.apply(key, &value, Source::File))
+                .map_err(|e| locate(e, path.display()))?;
+        }
+
+        for key in Key::ALL {
+            if let Some(value) = env(&key.env_var()) {
+                config
+                    .apply(key, &value, Source::Env)
+                    .map_err(|e| locate(e, key.env_var()))?;
+            }
+        }
+
+        for assignment in overrides {
+            let (name, value) = assignment.split_once('=').ok_or_else(|| {
+                AuthError::Config(format!("expected KEY=VALUE, got '{}'", assignment))
+            })?;
+            name.trim()
+                .parse()
+                .and_then(|key| config.apply(key, value, Source::Cli))
+                .map_err(|e| locate(e, "--set"))?;
+        }
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, key: Key, value: &str, source: Source) -> Result<()> {
+        self.settings.set(key, value)?;
+        self.sources.insert(key, source);
+        Ok(())
+    }
+
+    /// Where the effective value of `key` came from
+    pub fn source(&self, key: Key) -> Source {
+        self.sources.get(&key).copied().unwrap_or(Source::Default)
+    }
+
+    /// Validates `value` and stores it for `key` in the config file at `path`,
+    /// returning it as it will be shown from now on
+    /// Other settings in the file are kept, though comments are not
+    pub fn store(path: &Path, key: Key, value: &str) -> Result<String> {
+        let mut settings = Settings::default();
+        settings.set(key, value)?;
+
+        let mut table = read_file(path)?;
+        table.insert(key.name().to_string(), settings.toml_value(key));
+        write_file(path, &table)?;
+        Ok(settings.get(key))
+    }
+
+    /// Removes `key` from the config file at `path`; returns whether it was set
+    pub fn remove(path: &Path, key: Key) -> Result<bool> {
+        let mut table = read_file(path)?;
+        if table.remove(key.name()).is_none() {
+            return Ok(false);
+        }
+        write_file(path, &table)?;
+        Ok(true)
+    }
+}
+
+/// Reports a bad setting together with where it was found
+fn locate(e: AuthError, location: impl fmt::Display) -> AuthError {
+    let message = match e {
+        AuthError::InvalidParameter(message) | AuthError::Config(message) => message,
+        e => e.to_string(),
+    };
+    AuthError::Config(format!("{} in {}", message, location))
+}
+
+fn read_file(path: &Path) -> Result<toml::Table> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .parse()
+            .map_err(|e| AuthError::Config(format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_file(path: &Path, table: &toml::Table) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents = toml::to_string_pretty(table).map_err(|e| AuthError::Config(e.to_string()))?;
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn layers_file_env_and_cli_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, "digits = 8\nperiod = 60\nissuer = \"Home\"\n").unwrap();
+
+        let env = |name: &str| match name {
+            "R_AUTH_PERIOD" => Some("45".to_string()),
+            "R_AUTH_ISSUER" => Some("Office".to_string()),
+            _ => None,
+        };
+        let config = Config::load(&path, env, &["issuer=Laptop".to_string()]).unwrap();
+
+        assert_eq!(config.settings.digits, 8);
+        assert_eq!(config.source(Key::Digits), Source::File);
+        assert_eq!(config.settings.period, 45);
+        assert_eq!(config.source(Key::Period), Source::Env);
+        assert_eq!(config.settings.issuer, "Laptop");
+        assert_eq!(config.source(Key::Issuer), Source::Cli);
+        assert_eq!(config.settings.clear_after, DEFAULT_CLEAR_AFTER);
+        assert_eq!(config.source(Key::ClearAfter), Source::Default);
+    }
+
+    #[test]
+    fn rejects_invalid_and_unknown_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+
+        fs::write(&path, "digits = 9\n").unwrap();
+        assert!(matches!(
+            Config::load(&path, no_env, &[]),
+            Err(AuthError::Config(_))
+        ));
+
+        fs::write(&path, "colour = \"blue\"\n").unwrap();
+        assert!(Config::load(&path, no_env, &[]).is_err());
+
+        fs::write(&path, "").unwrap();
+        let env = |name: &str| (name == "R_AUTH_CONFIRM").then(|| "maybe".to_string());
+        assert!(Config::load(&path, env, &[]).is_err());
+        assert!(Config::load(&path, no_env, &["period".to_string()]).is_err());
+    }
+
+    #[test]
+    fn store_and_remove_keep_other_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+
+        Config::store(&path, Key::Algorithm, "sha256").unwrap();
+        Config::store(&path, Key::ShowQr, "no").unwrap();
+        assert!(Config::store(&path, Key::Period, "0").is_err());
+
+        let config = Config::load(&path, no_env, &[]).unwrap();
+        assert_eq!(config.settings.algorithm, Algorithm::Sha256);
+        assert!(!config.settings.show_qr);
+
+        assert!(Config::remove(&path, Key::ShowQr).unwrap());
+        assert!(!Config::remove(&path, Key::ShowQr).unwrap());
+        let config = Config::load(&path, no_env, &[]).unwrap();
+        assert!(config.settings.show_qr);
+        assert_eq!(config.source(Key::Algorithm), Source::File);
+    }
+}
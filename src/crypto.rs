@@ -1,74 +1,588 @@
+//! Encryption of the vault with an age identity kept in the system keyring, wrapped
+//! with a passphrase, or read from the user's own age identity file or SSH key, and
+//! optionally to the recipients of a shared vault
+
 use age::{x25519::Identity, Decryptor, Encryptor};
 use keyring::Entry;
-use secrecy::ExposeSecret;
+use secrecy::{ExposeSecret, SecretString};
+use std::cell::OnceCell;
 use std::fs;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use crate::error::{AuthError, Result};
+use crate::recipient::Recipient;
+use crate::storage;
 
 const SERVICE_NAME: &str = "r-auth";
 const USERNAME: &str = "encryption_key";
 
+/// File holding the passphrase-wrapped identity of a passphrase-protected vault
+const IDENTITY_FILE: &str = "identity.age";
+
+/// Where one encryption key is stored: an entry in the system keyring, an identity
+/// file when the key is protected with a passphrase, or a key file of the user's
+/// recorded in the metadata of the vault the slot belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    keyring_user: String,
+    dir: PathBuf,
+    identity_file: PathBuf,
+    key_file: Option<PathBuf>,
+}
+
+impl KeySlot {
+    /// The key shared by every vault that does not have its own, kept in `dir`
+    pub fn shared(dir: &Path) -> Self {
+        Self {
+            keyring_user: USERNAME.to_string(),
+            dir: dir.to_path_buf(),
+            identity_file: dir.join(IDENTITY_FILE),
+            key_file: None,
+        }
+    }
+
+    /// The key of the vault `name` alone, kept in the vault's directory `dir`
+    pub fn named(name: &str, dir: &Path) -> Self {
+        Self {
+            keyring_user: format!("{}:{}", USERNAME, name),
+            ..Self::shared(dir)
+        }
+    }
+
+    /// The key is `key_file`, as recorded in the vault metadata, rather than generated
+    pub fn with_key_file(mut self, key_file: Option<PathBuf>) -> Self {
+        self.key_file = key_file;
+        self
+    }
+
+    /// Location of the passphrase-wrapped identity used in [`KeyMode::Passphrase`]
+    pub fn identity_file(&self) -> &Path {
+        &self.identity_file
+    }
+
+    /// The key file used in [`KeyMode::KeyFile`]
+    pub fn key_file(&self) -> Option<&Path> {
+        self.key_file.as_deref()
+    }
+
+    /// The mode the key in this slot was created with
+    fn mode(&self) -> KeyMode {
+        match &self.key_file {
+            Some(path) => KeyMode::KeyFile(path.clone()),
+            None if self.identity_file.exists() => KeyMode::Passphrase,
+            None => KeyMode::Keyring,
+        }
+    }
+
+    /// Store of the generated key in `mode`, `None` for [`KeyMode::KeyFile`]
+    fn key_store(&self, mode: KeyMode, source: &PassphraseSource) -> Option<Box<dyn KeyStore>> {
+        match mode {
+            KeyMode::Keyring => Some(Box::new(KeyringStore::new(&self.keyring_user))),
+            KeyMode::Passphrase => Some(Box::new(PassphraseStore::new(
+                &self.identity_file,
+                source.clone(),
+            ))),
+            KeyMode::KeyFile(_) => None,
+        }
+    }
+
+    /// Directory the key is kept in, whose vault metadata records a key file
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Where the encryption key is kept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeyMode {
+    /// Identity stored in the system keyring
+    #[default]
+    Keyring,
+    /// Identity stored on disk, encrypted to an age scrypt passphrase recipient
+    Passphrase,
+    /// The user's own age identity file or SSH ed25519 or RSA private key, which
+    /// r-auth reads but never changes or deletes
+    KeyFile(PathBuf),
+}
+
+/// Where the passphrase of a passphrase-protected vault is read from
+#[derive(Debug, Clone, Default)]
+pub enum PassphraseSource {
+    /// Prompt on the terminal
+    #[default]
+    Prompt,
+    /// Read the first line from an inherited file descriptor, which must stay open
+    /// for as long as the source is used. It is borrowed, never closed
+    Fd(i32),
+    /// A passphrase already read, such as from [`PassphraseSource::Fd`] by
+    /// [`PassphraseSource::read_once`]
+    Given(SecretString),
+}
+
+impl PassphraseSource {
+    /// Reads the passphrase of an [`PassphraseSource::Fd`] source right away and
+    /// returns it as [`PassphraseSource::Given`], so that the descriptor is read only
+    /// once however many keys need it. Other sources are returned as they are
+    pub fn read_once(self) -> Result<Self> {
+        match self {
+            PassphraseSource::Fd(_) => Ok(PassphraseSource::Given(self.read("", false)?)),
+            source => Ok(source),
+        }
+    }
+
+    /// Reads a passphrase, asking with `prompt` on the terminal and twice when `confirm`
+    /// is set
+    fn read(&self, prompt: &str, confirm: bool) -> Result<SecretString> {
+        let passphrase = match self {
+            PassphraseSource::Prompt => {
+                let passphrase = rpassword::prompt_password(prompt)?;
+                if confirm && passphrase != rpassword::prompt_password("Confirm passphrase: ")? {
+                    return Err(AuthError::Passphrase("Passphrases do not match".into()));
+                }
+                passphrase
+            }
+            PassphraseSource::Fd(fd) => Self::read_fd(*fd)?,
+            PassphraseSource::Given(passphrase) => passphrase.expose_secret().to_string(),
+        };
+
+        if passphrase.is_empty() {
+            return Err(AuthError::Passphrase("Passphrase cannot be empty".into()));
+        }
+        Ok(SecretString::from(passphrase))
+    }
+
+    #[cfg(unix)]
+    fn read_fd(fd: i32) -> Result<String> {
+        use std::io::BufRead;
+        use std::mem::ManuallyDrop;
+        use std::os::unix::io::FromRawFd;
+
+        // Safety: the descriptor is borrowed, never owned: `ManuallyDrop` keeps the
+        // `File` from closing it, so its owner's handle stays valid and a second read
+        // cannot close a descriptor since reused by another file. The caller guarantees
+        // it is open, as documented on `PassphraseSource::Fd`
+        let file = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(fd) });
+        let mut line = String::new();
+        std::io::BufReader::new(&*file)
+            .read_line(&mut line)
+            .map_err(|e| AuthError::Passphrase(format!("Failed to read fd {}: {}", fd, e)))?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    #[cfg(not(unix))]
+    fn read_fd(_fd: i32) -> Result<String> {
+        Err(AuthError::Passphrase(
+            "Reading the passphrase from a file descriptor is only supported on Unix".into(),
+        ))
+    }
+}
+
+/// Persistent home of the age identity a vault is encrypted with
+pub trait KeyStore: Send {
+    /// Whether an identity has been stored
+    fn exists(&self) -> Result<bool>;
+
+    /// Reads the stored identity in its `AGE-SECRET-KEY-1...` encoding
+    fn load(&self) -> Result<SecretString>;
+
+    /// Stores `identity`, replacing the current one
+    fn save(&self, identity: &SecretString) -> Result<()>;
+
+    /// Deletes the stored identity
+    fn delete(&self) -> Result<()>;
+}
+
+/// Identity kept in the system keyring, as the r-auth service
+#[derive(Clone)]
+pub struct KeyringStore {
+    user: String,
+}
+
+impl KeyringStore {
+    /// Uses the keyring entry of `user`, such as `encryption_key` for the shared key
+    pub fn new(user: impl Into<String>) -> Self {
+        Self { user: user.into() }
+    }
+
+    fn entry(&self) -> Result<Entry> {
+        Entry::new(SERVICE_NAME, &self.user).map_err(|e| AuthError::Keyring(e.to_string()))
+    }
+}
+
+impl KeyStore for KeyringStore {
+    fn exists(&self) -> Result<bool> {
+        Ok(self.entry()?.get_password().is_ok())
+    }
+
+    fn load(&self) -> Result<SecretString> {
+        let identity = self
+            .entry()?
+            .get_password()
+            .map_err(|e| AuthError::Keyring(e.to_string()))?;
+        Ok(SecretString::from(identity))
+    }
+
+    fn save(&self, identity: &SecretString) -> Result<()> {
+        self.entry()?
+            .set_password(identity.expose_secret())
+            .map_err(|e| AuthError::Keyring(e.to_string()))
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.entry()?
+            .delete_credential()
+            .map_err(|e| AuthError::Keyring(e.to_string()))
+    }
+}
+
+/// Identity kept in a file, encrypted to an age scrypt passphrase recipient
+#[derive(Clone)]
+pub struct PassphraseStore {
+    path: PathBuf,
+    source: PassphraseSource,
+    /// Passphrase read on first use, so that it is asked for only once and a
+    /// replacement identity is wrapped with the same one
+    passphrase: OnceCell<SecretString>,
+}
+
+impl PassphraseStore {
+    /// Uses the identity file at `path`, reading its passphrase from `source` when
+    /// first needed
+    pub fn new(path: impl Into<PathBuf>, source: PassphraseSource) -> Self {
+        Self {
+            path: path.into(),
+            source,
+            passphrase: OnceCell::new(),
+        }
+    }
+
+    /// Uses the identity file at `path` with a passphrase the caller already has
+    pub fn with_passphrase(path: impl Into<PathBuf>, passphrase: SecretString) -> Self {
+        Self {
+            passphrase: OnceCell::from(passphrase),
+            ..Self::new(path, PassphraseSource::default())
+        }
+    }
+
+    fn passphrase(&self, confirm: bool) -> Result<&SecretString> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = self.source.read("Vault passphrase: ", confirm)?;
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+}
+
+impl KeyStore for PassphraseStore {
+    fn exists(&self) -> Result<bool> {
+        Ok(self.path.exists())
+    }
+
+    fn load(&self) -> Result<SecretString> {
+        let wrapped = fs::read(&self.path)?;
+        let decryptor = Decryptor::new(&wrapped[..])?;
+        if !decryptor.is_scrypt() {
+            return Err(AuthError::Decryption("Invalid identity file".into()));
+        }
+
+        let passphrase = age::scrypt::Identity::new(self.passphrase(false)?.clone());
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&passphrase as &dyn age::Identity))
+            .map_err(|e| AuthError::Passphrase(format!("Failed to unlock vault: {}", e)))?;
+
+        let mut identity = String::new();
+        reader.read_to_string(&mut identity)?;
+        Ok(SecretString::from(identity))
+    }
+
+    fn save(&self, identity: &SecretString) -> Result<()> {
+        let passphrase = self.passphrase(true)?.clone();
+        let wrapped = Crypto::encrypt_with(
+            Encryptor::with_user_passphrase(passphrase),
+            identity.expose_secret().as_bytes(),
+        )?;
+        // No backup is kept: the previous identity must not outlive its replacement
+        storage::replace_atomic(&self.path, &wrapped)
+    }
+
+    fn delete(&self) -> Result<()> {
+        fs::remove_file(&self.path)
+            .map_err(|e| AuthError::StorageFile(format!("Failed to delete identity file: {}", e)))
+    }
+}
+
+/// Identity kept in memory only, for tests and for embedding r-auth in programs that
+/// manage keys themselves. Clones share the same identity
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    identity: Arc<Mutex<Option<String>>>,
+}
+
+impl MemoryStore {
+    fn identity(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        // The guarded value is replaced whole, so a panicking holder cannot corrupt it
+        self.identity
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl KeyStore for MemoryStore {
+    fn exists(&self) -> Result<bool> {
+        Ok(self.identity().is_some())
+    }
+
+    fn load(&self) -> Result<SecretString> {
+        self.identity()
+            .clone()
+            .map(SecretString::from)
+            .ok_or(AuthError::KeyNotFound)
+    }
+
+    fn save(&self, identity: &SecretString) -> Result<()> {
+        *self.identity() = Some(identity.expose_secret().to_string());
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<()> {
+        *self.identity() = None;
+        Ok(())
+    }
+}
+
+/// An identity data can be decrypted with, and the recipient encrypting to it
+struct KeyPair {
+    identity: Box<dyn age::Identity + Send>,
+    recipient: Recipient,
+}
+
+impl KeyPair {
+    fn generated(identity: Identity) -> Self {
+        Self {
+            recipient: Recipient::X25519(identity.to_public()),
+            identity: Box::new(identity),
+        }
+    }
+
+    /// Reads the identities in `path`: an age identity file with one
+    /// `AGE-SECRET-KEY-1...` per line, or an SSH ed25519 or RSA private key whose
+    /// passphrase, if it has one, is read from `source`. At least one is returned
+    fn read_file(path: &Path, source: &PassphraseSource) -> Result<Vec<Self>> {
+        let invalid =
+            |e: &dyn std::fmt::Display| AuthError::KeyParse(format!("{}: {}", path.display(), e));
+        let contents = fs::read_to_string(path).map_err(|e| invalid(&e))?;
+
+        if contents.trim_start().starts_with("-----BEGIN") {
+            let identity = age::ssh::Identity::from_buffer(contents.as_bytes(), None)
+                .map_err(|e| invalid(&e))?;
+            let recipient = age::ssh::Recipient::try_from(identity.clone())
+                .map_err(|_| invalid(&"unsupported SSH key type"))?;
+            let key = match identity {
+                age::ssh::Identity::Unencrypted(key) => key,
+                age::ssh::Identity::Encrypted(key) => {
+                    let prompt = format!("Passphrase for {}: ", path.display());
+                    key.decrypt(source.read(&prompt, false)?).map_err(|e| {
+                        AuthError::Passphrase(format!("Failed to unlock {}: {}", path.display(), e))
+                    })?
+                }
+                age::ssh::Identity::Unsupported(_) => {
+                    return Err(invalid(&"unsupported SSH key type"))
+                }
+            };
+            return Ok(vec![Self {
+                identity: Box::new(age::ssh::Identity::Unencrypted(key)),
+                recipient: Recipient::Ssh(recipient, None),
+            }]);
+        }
+
+        let mut keys = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            keys.push(Self::generated(line.parse().map_err(|e| invalid(&e))?));
+        }
+        if keys.is_empty() {
+            return Err(invalid(&"no identities found"));
+        }
+        Ok(keys)
+    }
+}
+
+/// Encrypts and decrypts vault contents with the user's age identity
 #[derive(Default)]
-pub struct Crypto;
+pub struct Crypto {
+    passphrase_source: PassphraseSource,
+    /// Where the key is stored when it lives in the keyring, an identity file or a key file
+    key_slot: Option<KeySlot>,
+    /// Store a generated key is read from, `None` when the identity was handed over
+    /// directly or the key is a key file
+    key_store: Option<Box<dyn KeyStore>>,
+    /// The user's key file in [`KeyMode::KeyFile`], set from the slot or by
+    /// [`Crypto::init`]
+    key_file: OnceCell<PathBuf>,
+    /// Key loaded on first use, so the passphrase is asked for only once
+    key: OnceCell<KeyPair>,
+    /// Other identities tried first when decrypting, such as an SSH key that is a
+    /// recipient of a shared vault
+    identities: Vec<KeyPair>,
+    /// Recipients to encrypt to instead of the key alone
+    recipients: Vec<Recipient>,
+}
 
 impl Crypto {
-    fn get_keyring_entry() -> Result<Entry> {
-        Entry::new(SERVICE_NAME, USERNAME).map_err(|e| AuthError::Keyring(e.to_string()))
+    fn key_store(&self) -> Result<&dyn KeyStore> {
+        self.key_store.as_deref().ok_or(AuthError::KeyNotFound)
     }
 
+    /// Whether an encryption key has been set up; identities given to
+    /// [`Crypto::with_identity_file`] do not count
     pub fn key_exists(&self) -> Result<bool> {
-        let entry = Self::get_keyring_entry()?;
-        Ok(entry.get_password().is_ok())
+        if self.key.get().is_some() || self.key_file.get().is_some() {
+            return Ok(true);
+        }
+        self.key_store()?.exists()
     }
 
-    pub fn new() -> Result<Self> {
-        let mut accounts_path = dirs::config_dir().ok_or(AuthError::ConfigDir)?;
-        accounts_path.push("r-auth");
-        fs::create_dir_all(&accounts_path).map_err(|e| {
-            AuthError::StorageFile(format!("Failed to create config directory: {}", e))
-        })?;
+    /// Uses the key stored in `key_slot`, set up with [`Crypto::init`]
+    pub fn new(key_slot: KeySlot) -> Self {
+        Self {
+            key_slot: Some(key_slot),
+            ..Self::default()
+        }
+        .with_passphrase_source(PassphraseSource::default())
+    }
 
-        Ok(Self)
+    /// Uses the key kept in `key_store`
+    pub fn from_key_store(key_store: impl KeyStore + 'static) -> Self {
+        Self {
+            key_store: Some(Box::new(key_store)),
+            ..Self::default()
+        }
     }
 
-    pub fn init(&self) -> Result<()> {
-        let entry = Self::get_keyring_entry()?;
+    /// Uses `identity` directly instead of the keyring or identity file
+    pub fn from_identity(identity: Identity) -> Self {
+        Self {
+            key: OnceCell::from(KeyPair::generated(identity)),
+            ..Self::default()
+        }
+    }
+
+    /// Where the passphrase is read from in [`KeyMode::Passphrase`], and for a
+    /// passphrase-protected SSH key
+    pub fn with_passphrase_source(mut self, source: PassphraseSource) -> Self {
+        if let Some(slot) = &self.key_slot {
+            self.key_store = slot.key_store(slot.mode(), &source);
+            self.key_file = slot
+                .key_file
+                .clone()
+                .map(OnceCell::from)
+                .unwrap_or_default();
+        }
+        self.passphrase_source = source;
+        self
+    }
+
+    /// Also decrypts with the identities in `path`: an age identity file with one
+    /// `AGE-SECRET-KEY-1...` per line, or an SSH ed25519 or RSA private key
+    /// A passphrase-protected SSH key is unlocked right away, with the passphrase
+    /// source set beforehand
+    pub fn with_identity_file(mut self, path: &Path) -> Result<Self> {
+        self.identities
+            .extend(KeyPair::read_file(path, &self.passphrase_source)?);
+        Ok(self)
+    }
 
-        // Check if key already exists
-        if entry.get_password().is_ok() {
+    /// Whether identities were given to [`Crypto::with_identity_file`]
+    pub fn has_identity_files(&self) -> bool {
+        !self.identities.is_empty()
+    }
+
+    /// Recipients data is encrypted to, or none when it is encrypted to the key alone
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    /// Encrypts to `recipients`, which should include the key for it to stay able to
+    /// decrypt, or to the key alone when empty
+    pub(crate) fn set_recipients(&mut self, recipients: Vec<Recipient>) {
+        self.recipients = recipients;
+    }
+
+    /// The recipient matching the key, loading it if needed
+    pub fn public_key(&self) -> Result<Recipient> {
+        Ok(self.load_key()?.recipient.clone())
+    }
+
+    /// The user's key file when the key is one, see [`KeyMode::KeyFile`]
+    pub fn key_file(&self) -> Option<&Path> {
+        self.key_file.get().map(PathBuf::as_path)
+    }
+
+    /// Sets up the key as `mode` describes: generates a new identity and stores it, or
+    /// checks that the key file can be read. A generated key is saved to the store given
+    /// to [`Crypto::from_key_store`] whatever the `mode`
+    /// Returns the canonical path of the key file, for the caller to record alongside
+    /// the vault, or `None` for a generated key
+    /// Fails with [`AuthError::KeyExists`] if a key has already been set up
+    pub fn init(&self, mode: KeyMode) -> Result<Option<PathBuf>> {
+        if self.key_exists()? {
             return Err(AuthError::KeyExists);
         }
 
-        // Generate new key
-        let key = Identity::generate();
-        entry
-            .set_password(key.to_string().expose_secret())
-            .map_err(|e| AuthError::Keyring(e.to_string()))?;
+        if let KeyMode::KeyFile(path) = &mode {
+            let path = fs::canonicalize(path)
+                .map_err(|e| AuthError::KeyParse(format!("{}: {}", path.display(), e)))?;
+            let key = KeyPair::read_file(&path, &self.passphrase_source)?.remove(0);
+            let _ = self.key_file.set(path.clone());
+            let _ = self.key.set(key);
+            return Ok(Some(path));
+        }
 
-        println!("Encryption key generated and stored securely in system keyring");
-        Ok(())
+        let key = Identity::generate();
+        match self
+            .key_slot
+            .as_ref()
+            .and_then(|slot| slot.key_store(mode, &self.passphrase_source))
+        {
+            Some(store) => store.save(&key.to_string())?,
+            None => self.key_store()?.save(&key.to_string())?,
+        }
+        let _ = self.key.set(KeyPair::generated(key));
+        Ok(None)
     }
 
-    fn load_key(&self) -> Result<Identity> {
-        let entry = Self::get_keyring_entry()?;
-        let key_data = entry
-            .get_password()
-            .map_err(|e| AuthError::Keyring(e.to_string()))?;
+    fn load_key(&self) -> Result<&KeyPair> {
+        if let Some(key) = self.key.get() {
+            return Ok(key);
+        }
 
-        key_data
-            .parse::<Identity>()
-            .map_err(|e| AuthError::KeyParse(e.to_string()))
+        let key = match self.key_file.get() {
+            Some(path) => KeyPair::read_file(path, &self.passphrase_source)?.remove(0),
+            None => KeyPair::generated(
+                self.key_store()?
+                    .load()?
+                    .expose_secret()
+                    .parse::<Identity>()
+                    .map_err(|e| AuthError::KeyParse(e.to_string()))?,
+            ),
+        };
+        Ok(self.key.get_or_init(|| key))
     }
 
-    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let key = self.load_key()?;
-        let recipient = key.to_public();
+    /// Stores `identity` in place of the current key and uses it from now on
+    /// A passphrase-protected key keeps its passphrase
+    pub(crate) fn replace_key(&mut self, identity: Identity) -> Result<()> {
+        self.key_store()?.save(&identity.to_string())?;
+        self.key = OnceCell::from(KeyPair::generated(identity));
+        Ok(())
+    }
 
+    fn encrypt_with(encryptor: Encryptor, data: &[u8]) -> Result<Vec<u8>> {
         let mut encrypted = vec![];
-        let encryptor = Encryptor::with_recipients(vec![Box::new(recipient)])
-            .expect("Failed to create encryptor");
-
         let mut writer = encryptor
             .wrap_output(Box::new(&mut encrypted))
             .map_err(|e| AuthError::Encryption(e.to_string()))?;
@@ -79,27 +593,55 @@ impl Crypto {
         Ok(encrypted)
     }
 
+    /// Encrypts `data` to the recipients, or to the key when there are none
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let encryptor = if self.recipients.is_empty() {
+            let recipient = &self.load_key()?.recipient;
+            Encryptor::with_recipients(std::iter::once(recipient.as_age()))
+        } else {
+            Encryptor::with_recipients(self.recipients.iter().map(Recipient::as_age))
+        };
+        let encryptor = encryptor.map_err(|e| AuthError::Encryption(e.to_string()))?;
+        Self::encrypt_with(encryptor, data)
+    }
+
+    /// Decrypts data produced by [`Crypto::encrypt`], with the identities given to
+    /// [`Crypto::with_identity_file`] and then with the key, if one has been set up
     pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
-        let key = self.load_key()?;
+        if !self.identities.is_empty() {
+            match Self::decrypt_with(encrypted_data, &self.identities) {
+                Err(AuthError::Decryption(_)) if self.key_exists()? => {}
+                result => return result,
+            }
+        }
 
-        let decryptor = match Decryptor::new(encrypted_data)? {
-            Decryptor::Recipients(d) => d,
-            _ => return Err(AuthError::Decryption("Invalid decryptor type".into())),
-        };
+        Self::decrypt_with(encrypted_data, std::slice::from_ref(self.load_key()?))
+    }
+
+    fn decrypt_with(encrypted_data: &[u8], keys: &[KeyPair]) -> Result<Vec<u8>> {
+        let decryptor = Decryptor::new(encrypted_data)?;
+        if decryptor.is_scrypt() {
+            return Err(AuthError::Decryption("Invalid decryptor type".into()));
+        }
 
         let mut decrypted = vec![];
+        let identities = keys
+            .iter()
+            .map(|key| key.identity.as_ref() as &dyn age::Identity);
         let mut reader = decryptor
-            .decrypt(std::iter::once(&key as &dyn age::Identity))
+            .decrypt(identities)
             .map_err(|e| AuthError::Decryption(e.to_string()))?;
 
         reader.read_to_end(&mut decrypted)?;
         Ok(decrypted)
     }
 
+    /// Deletes the key; anything encrypted with it can no longer be read
+    /// A key file is never deleted; forgetting it is up to whoever recorded it
     pub fn reset(&self) -> Result<()> {
-        let entry = Self::get_keyring_entry()?;
-        entry
-            .delete_credential()
-            .map_err(|e| AuthError::Keyring(e.to_string()))
+        if self.key_file.get().is_some() {
+            return Ok(());
+        }
+        self.key_store()?.delete()
     }
 }
@@ -1,3 +1,5 @@
+//! Errors of every r-auth operation
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,6 +16,21 @@ pub enum AuthError {
     #[error("Invalid secret key: {0}")]
     InvalidSecret(String),
 
+    #[error("Unsupported algorithm: {0}. Expected SHA1, SHA256 or SHA512")]
+    InvalidAlgorithm(String),
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Invalid otpauth URI: {0}")]
+    InvalidUri(String),
+
+    #[error("Invalid Google Authenticator export: {0}")]
+    InvalidMigration(String),
+
+    #[error("Invalid backup file: {0}")]
+    InvalidBackup(String),
+
     #[error("QR code error: {0}")]
     QrCode(String),
 
@@ -44,11 +61,127 @@ pub enum AuthError {
     #[error("Invalid storage data: {0}")]
     InvalidStorage(String),
 
+    #[error(
+        "Storage file is unreadable ({0}). Run 'r-auth recover' to restore the previous backup"
+    )]
+    CorruptStorage(String),
+
+    #[error("Storage is locked by another r-auth process ({0}). Try again once it has finished")]
+    StorageLocked(String),
+
     #[error("Keyring error: {0}")]
     Keyring(String),
 
+    #[error("Passphrase error: {0}")]
+    Passphrase(String),
+
     #[error("Account '{0}' already exists. Use --force to replace it")]
     AccountExists(String),
+
+    #[error("Account '{0}' not found")]
+    AccountNotFound(String),
+
+    #[error("Clipboard error: {0}")]
+    Clipboard(String),
+
+    #[error("'{0}' matches several accounts: {}. Use the full name", .1.join(", "))]
+    AmbiguousAccount(String, Vec<String>),
+
+    #[error("Code rejected: {0}")]
+    CodeRejected(String),
+
+    #[error("Vault '{0}' not found. Create it with 'r-auth vault create'")]
+    VaultNotFound(String),
+
+    #[error("Vault '{0}' already exists")]
+    VaultExists(String),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Recipient error: {0}")]
+    Recipient(String),
+}
+
+impl AuthError {
+    /// Stable identifier of the error, used in machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthError::Io(_) => "io",
+            AuthError::Json(_) => "json",
+            AuthError::Base32DecodeError => "base32_decode",
+            AuthError::InvalidSecret(_) => "invalid_secret",
+            AuthError::InvalidAlgorithm(_) => "invalid_algorithm",
+            AuthError::InvalidParameter(_) => "invalid_parameter",
+            AuthError::InvalidUri(_) => "invalid_uri",
+            AuthError::InvalidMigration(_) => "invalid_migration",
+            AuthError::InvalidBackup(_) => "invalid_backup",
+            AuthError::QrCode(_) => "qr_code",
+            AuthError::Encryption(_) => "encryption",
+            AuthError::Decryption(_) => "decryption",
+            AuthError::KeyParse(_) => "key_parse",
+            AuthError::KeyExists => "key_exists",
+            AuthError::ConfigDir => "config_dir",
+            AuthError::Age(_) => "age",
+            AuthError::StorageFile(_) => "storage_file",
+            AuthError::KeyNotFound => "key_not_found",
+            AuthError::InvalidStorage(_) => "invalid_storage",
+            AuthError::CorruptStorage(_) => "corrupt_storage",
+            AuthError::StorageLocked(_) => "storage_locked",
+            AuthError::Keyring(_) => "keyring",
+            AuthError::Passphrase(_) => "passphrase",
+            AuthError::AccountExists(_) => "account_exists",
+            AuthError::AccountNotFound(_) => "account_not_found",
+            AuthError::Clipboard(_) => "clipboard",
+            AuthError::AmbiguousAccount(..) => "ambiguous_account",
+            AuthError::CodeRejected(_) => "code_rejected",
+            AuthError::VaultNotFound(_) => "vault_not_found",
+            AuthError::VaultExists(_) => "vault_exists",
+            AuthError::Config(_) => "config",
+            AuthError::Recipient(_) => "recipient",
+        }
+    }
+
+    /// Process exit code for the error
+    /// Codes are part of the CLI contract: existing values never change and new
+    /// variants take the next free number. 1 is left for failures outside `AuthError`
+    /// and 2 for usage errors reported by clap
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AuthError::Io(_) => 10,
+            AuthError::Json(_) => 11,
+            AuthError::Base32DecodeError => 12,
+            AuthError::InvalidSecret(_) => 13,
+            AuthError::InvalidAlgorithm(_) => 14,
+            AuthError::InvalidParameter(_) => 15,
+            AuthError::InvalidUri(_) => 16,
+            AuthError::InvalidMigration(_) => 17,
+            AuthError::InvalidBackup(_) => 18,
+            AuthError::QrCode(_) => 19,
+            AuthError::Encryption(_) => 20,
+            AuthError::Decryption(_) => 21,
+            AuthError::KeyParse(_) => 22,
+            AuthError::KeyExists => 23,
+            AuthError::ConfigDir => 24,
+            AuthError::Age(_) => 25,
+            AuthError::StorageFile(_) => 26,
+            AuthError::KeyNotFound => 27,
+            AuthError::InvalidStorage(_) => 28,
+            AuthError::CorruptStorage(_) => 29,
+            AuthError::StorageLocked(_) => 30,
+            AuthError::Keyring(_) => 31,
+            AuthError::Passphrase(_) => 32,
+            AuthError::AccountExists(_) => 33,
+            AuthError::AccountNotFound(_) => 34,
+            AuthError::Clipboard(_) => 35,
+            AuthError::AmbiguousAccount(..) => 36,
+            AuthError::CodeRejected(_) => 37,
+            AuthError::VaultNotFound(_) => 38,
+            AuthError::VaultExists(_) => 39,
+            AuthError::Config(_) => 40,
+            AuthError::Recipient(_) => 41,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, AuthError>;
@@ -0,0 +1,473 @@
+//! Aegis Authenticator vault format
+//!
+//! Layout follows <https://github.com/beemdevelopment/Aegis/blob/master/docs/vault.md>.
+//! Encrypted vaults store the database as AES-256-GCM ciphertext under a random master
+//! key, which is itself wrapped by a key derived from the password with scrypt.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{aes_gcm_open, invalid_backup, issuer_and_name, random_uuid, BackupEntry, Imported};
+use crate::account::{Account, OtpKind};
+use crate::error::{AuthError, Result};
+
+const VAULT_VERSION: u32 = 1;
+const DB_VERSION: u32 = 2;
+
+/// Slot type of a password-derived key
+const SLOT_PASSWORD: u32 = 1;
+
+/// scrypt parameters used by Aegis for password slots
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Vault {
+    pub version: u32,
+    pub header: Header,
+    pub db: Db,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub slots: Option<Vec<Slot>>,
+    pub params: Option<KeyParams>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyParams {
+    pub nonce: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Slot {
+    #[serde(rename = "type")]
+    pub kind: u32,
+    pub uuid: String,
+    /// Wrapped master key, hex encoded
+    pub key: String,
+    pub key_params: KeyParams,
+    #[serde(default)]
+    pub n: Option<u64>,
+    #[serde(default)]
+    pub r: Option<u32>,
+    #[serde(default)]
+    pub p: Option<u32>,
+    #[serde(default)]
+    pub salt: Option<String>,
+    #[serde(default)]
+    pub repaired: Option<bool>,
+}
+
+/// Plain database, or its base64 ciphertext in encrypted vaults
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Db {
+    Plain(Database),
+    Encrypted(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Database {
+    pub version: u32,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub uuid: String,
+    pub name: String,
+    pub issuer: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub icon: Option<String>,
+    pub info: Info,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Info {
+    pub secret: String,
+    pub algo: String,
+    pub digits: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<u64>,
+}
+
+/// Builds the Aegis database for the given accounts, sorted by name
+pub fn to_database(accounts: &HashMap<String, Account>) -> Database {
+    let mut names: Vec<&String> = accounts.keys().collect();
+    names.sort();
+
+    let entries = names
+        .into_iter()
+        .map(|name| {
+            let account = &accounts[name];
+            let (issuer, name) = issuer_and_name(name, account);
+            let (kind, period, counter) = match account.kind {
+                OtpKind::Totp => ("totp", Some(account.period), None),
+                OtpKind::Hotp => ("hotp", None, Some(account.counter)),
+            };
+
+            Entry {
+                kind: kind.to_string(),
+                uuid: random_uuid(),
+                name,
+                issuer,
+                note: account.notes.clone().unwrap_or_default(),
+                favorite: false,
+                icon: None,
+                info: Info {
+                    // Aegis stores secrets without Base32 padding
+                    secret: account.secret.trim_end_matches('=').to_string(),
+                    algo: account.algorithm.to_string(),
+                    digits: account.digits,
+                    period,
+                    counter,
+                },
+            }
+        })
+        .collect();
+
+    Database {
+        version: DB_VERSION,
+        entries,
+    }
+}
+
+/// Serializes accounts as an Aegis vault, encrypted with `password` when given
+pub fn export(accounts: &HashMap<String, Account>, password: Option<&str>) -> Result<String> {
+    let db = to_database(accounts);
+
+    let vault = match password {
+        None => Vault {
+            version: VAULT_VERSION,
+            header: Header {
+                slots: None,
+                params: None,
+            },
+            db: Db::Plain(db),
+        },
+        Some(password) => encrypt(&db, password)?,
+    };
+
+    serde_json::to_string_pretty(&vault).map_err(AuthError::from)
+}
+
+fn encrypt(db: &Database, password: &str) -> Result<Vault> {
+    let mut rng = rand::thread_rng();
+
+    let mut master_key = [0u8; 32];
+    rng.fill_bytes(&mut master_key);
+    let mut salt = [0u8; 32];
+    rng.fill_bytes(&mut salt);
+
+    let password_key = derive_key(password, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let (wrapped_key, key_params) = seal(&password_key, &master_key)?;
+
+    let plaintext = serde_json::to_vec(db)?;
+    let (ciphertext, params) = seal(&master_key, &plaintext)?;
+
+    Ok(Vault {
+        version: VAULT_VERSION,
+        header: Header {
+            slots: Some(vec![Slot {
+                kind: SLOT_PASSWORD,
+                uuid: random_uuid(),
+                key: hex::encode(wrapped_key),
+                key_params,
+                n: Some(1 << SCRYPT_LOG_N),
+                r: Some(SCRYPT_R),
+                p: Some(SCRYPT_P),
+                salt: Some(hex::encode(salt)),
+                repaired: Some(true),
+            }]),
+            params: Some(params),
+        },
+        db: Db::Encrypted(base64::engine::general_purpose::STANDARD.encode(ciphertext)),
+    })
+}
+
+/// Reads an Aegis vault, prompting for the password if it is encrypted
+pub fn import(data: &[u8], password: &dyn Fn() -> Result<String>) -> Result<Imported> {
+    let vault: Vault = serde_json::from_slice(data).map_err(invalid_backup)?;
+
+    let db = match vault.db {
+        Db::Plain(db) => db,
+        Db::Encrypted(db) => decrypt(&vault.header, &db, &password()?)?,
+    };
+
+    let mut imported = Imported::default();
+    for entry in db.entries {
+        imported.push(BackupEntry {
+            kind: &entry.kind,
+            issuer: &entry.issuer,
+            name: &entry.name,
+            secret: &entry.info.secret,
+            algorithm: &entry.info.algo,
+            digits: entry.info.digits,
+            period: entry.info.period.unwrap_or(30),
+            counter: entry.info.counter.unwrap_or(0),
+            note: Some(entry.note),
+            tags: Vec::new(),
+        });
+    }
+    Ok(imported)
+}
+
+/// Decrypts the database of an encrypted vault: the first password slot the password
+/// opens yields the master key, which decrypts the database
+pub fn decrypt(header: &Header, db: &str, password: &str) -> Result<Database> {
+    let params = header
+        .params
+        .as_ref()
+        .ok_or_else(|| invalid_backup("missing database parameters"))?;
+
+    let master_key = header
+        .slots
+        .iter()
+        .flatten()
+        .filter(|slot| slot.kind == SLOT_PASSWORD)
+        .find_map(|slot| unwrap_slot(slot, password).ok())
+        .ok_or_else(|| AuthError::Decryption("wrong password or no password slot".into()))?;
+
+    let ciphertext = base64::engine::general_purpose::STANDARD
+        .decode(db)
+        .map_err(invalid_backup)?;
+    let plaintext = open(&master_key, params, &ciphertext)?;
+    serde_json::from_slice(&plaintext).map_err(invalid_backup)
+}
+
+fn unwrap_slot(slot: &Slot, password: &str) -> Result<Vec<u8>> {
+    let (Some(n), Some(r), Some(p), Some(salt)) = (slot.n, slot.r, slot.p, &slot.salt) else {
+        return Err(invalid_backup("incomplete password slot"));
+    };
+    if !n.is_power_of_two() {
+        return Err(invalid_backup("scrypt N is not a power of two"));
+    }
+
+    let salt = hex::decode(salt).map_err(invalid_backup)?;
+    let key = derive_key(password, &salt, n.trailing_zeros() as u8, r, p)?;
+    let wrapped = hex::decode(&slot.key).map_err(invalid_backup)?;
+    open(&key, &slot.key_params, &wrapped)
+}
+
+/// Reverses [`seal`]
+fn open(key: &[u8], params: &KeyParams, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = hex::decode(&params.nonce).map_err(invalid_backup)?;
+    let mut sealed = ciphertext.to_vec();
+    sealed.extend(hex::decode(&params.tag).map_err(invalid_backup)?);
+    aes_gcm_open(key, &nonce, &sealed)
+}
+
+fn derive_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<[u8; 32]> {
+    let params =
+        scrypt::Params::new(log_n, r, p).map_err(|e| AuthError::Encryption(e.to_string()))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| AuthError::Encryption(e.to_string()))?;
+    Ok(key)
+}
+
+/// AES-256-GCM encrypts `plaintext`, returning the ciphertext and its nonce and tag
+/// (Aegis keeps the tag separate from the ciphertext)
+fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, KeyParams)> {
+    let cipher =
+        Aes256Gcm::new_from_slice(key).map_err(|e| AuthError::Encryption(e.to_string()))?;
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut sealed = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &[],
+            },
+        )
+        .map_err(|e| AuthError::Encryption(e.to_string()))?;
+    let tag = sealed.split_off(sealed.len() - TAG_LEN);
+
+    Ok((
+        sealed,
+        KeyParams {
+            nonce: hex::encode(nonce),
+            tag: hex::encode(tag),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountParams;
+    use crate::totp::{Algorithm, TOTP};
+
+    fn accounts() -> HashMap<String, Account> {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            "GitHub:alice".to_string(),
+            Account::new("JBSWY3DPEHPK3PXP".into(), AccountParams::default()),
+        );
+        accounts.insert(
+            "AWS".to_string(),
+            Account {
+                issuer: Some("Amazon".into()),
+                ..Account::new(
+                    "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".into(),
+                    AccountParams {
+                        algorithm: Algorithm::Sha256,
+                        digits: 8,
+                        period: 60,
+                        ..Default::default()
+                    },
+                )
+            },
+        );
+        accounts
+    }
+
+    /// Checks that every exported entry produces the same codes as the original account
+    fn assert_codes_match(db: &Database, accounts: &HashMap<String, Account>) {
+        assert_eq!(db.entries.len(), accounts.len());
+
+        for entry in &db.entries {
+            let original = accounts
+                .values()
+                .find(|a| a.secret.trim_end_matches('=') == entry.info.secret)
+                .unwrap();
+            let exported = TOTP::new(&entry.info.secret)
+                .unwrap()
+                .with_algorithm(entry.info.algo.parse().unwrap())
+                .with_digits(entry.info.digits)
+                .unwrap()
+                .with_period(entry.info.period.unwrap())
+                .unwrap();
+
+            for timestamp in [0, 59, 1111111109, 2000000000] {
+                assert_eq!(
+                    exported.generate(timestamp).unwrap(),
+                    original.totp().unwrap().generate(timestamp).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn plain_export_round_trips() {
+        let accounts = accounts();
+        let json = export(&accounts, None).unwrap();
+        let vault: Vault = serde_json::from_str(&json).unwrap();
+
+        assert!(vault.header.slots.is_none());
+        let Db::Plain(db) = vault.db else {
+            panic!("database is encrypted");
+        };
+
+        let github = db.entries.iter().find(|e| e.name == "alice").unwrap();
+        assert_eq!(github.issuer, "GitHub");
+        let aws = db.entries.iter().find(|e| e.name == "AWS").unwrap();
+        assert_eq!(aws.issuer, "Amazon");
+        assert_eq!(aws.info.algo, "SHA256");
+
+        assert_codes_match(&db, &accounts);
+    }
+
+    #[test]
+    fn encrypted_export_round_trips() {
+        let accounts = accounts();
+        let json = export(&accounts, Some("correct horse")).unwrap();
+        let vault: Vault = serde_json::from_str(&json).unwrap();
+
+        let slot = &vault.header.slots.as_ref().unwrap()[0];
+        assert_eq!(slot.kind, SLOT_PASSWORD);
+        assert_eq!(slot.n, Some(32768));
+
+        let Db::Encrypted(db) = &vault.db else {
+            panic!("database is not encrypted");
+        };
+        assert!(decrypt(&vault.header, db, "wrong").is_err());
+
+        let db = decrypt(&vault.header, db, "correct horse").unwrap();
+        assert_eq!(db.version, DB_VERSION);
+        assert_codes_match(&db, &accounts);
+    }
+
+    #[test]
+    fn imports_exported_vaults() {
+        let accounts = accounts();
+        for password in [None, Some("hunter2")] {
+            let json = export(&accounts, password).unwrap();
+            let imported = import(json.as_bytes(), &|| Ok("hunter2".to_string())).unwrap();
+
+            assert!(imported.unsupported.is_empty());
+            let mut names: Vec<&str> = imported.accounts.iter().map(|(n, _)| n.as_str()).collect();
+            names.sort();
+            assert_eq!(names, ["Amazon:AWS", "GitHub:alice"]);
+
+            let (_, aws) = &imported
+                .accounts
+                .iter()
+                .find(|(n, _)| n == "Amazon:AWS")
+                .unwrap();
+            assert_eq!(aws.secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+            assert_eq!(aws.digits, 8);
+            assert_eq!(aws.period, 60);
+        }
+    }
+
+    #[test]
+    fn reports_unsupported_entry_types() {
+        let json = r#"{
+            "version": 1,
+            "header": {"slots": null, "params": null},
+            "db": {"version": 2, "entries": [
+                {"type": "steam", "uuid": "1", "name": "me", "issuer": "Steam",
+                 "info": {"secret": "JBSWY3DPEHPK3PXP", "algo": "SHA1", "digits": 5, "period": 30}},
+                {"type": "totp", "uuid": "2", "name": "me", "issuer": "GitHub", "note": "work",
+                 "info": {"secret": "JBSWY3DPEHPK3PXP", "algo": "SHA1", "digits": 6, "period": 30}}
+            ]}
+        }"#;
+        let imported = import(json.as_bytes(), &|| panic!("not encrypted")).unwrap();
+
+        assert_eq!(imported.unsupported.len(), 1);
+        assert_eq!(imported.unsupported[0].0, "Steam:me");
+        assert_eq!(imported.accounts[0].0, "GitHub:me");
+        assert_eq!(imported.accounts[0].1.notes.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn hotp_entries_carry_counter() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            "VPN".to_string(),
+            Account::new(
+                "JBSWY3DPEHPK3PXP".into(),
+                AccountParams {
+                    kind: OtpKind::Hotp,
+                    counter: 5,
+                    ..Default::default()
+                },
+            ),
+        );
+
+        let db = to_database(&accounts);
+        assert_eq!(db.entries[0].kind, "hotp");
+        assert_eq!(db.entries[0].info.counter, Some(5));
+        assert_eq!(db.entries[0].info.period, None);
+    }
+}
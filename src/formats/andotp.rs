@@ -0,0 +1,168 @@
+//! andOTP backup format
+//!
+//! Plain backups are a JSON array of entries. Encrypted backups (`.json.aes`) are
+//! AES-256-GCM ciphertext laid out as `iterations (u32 BE) | salt (12) | nonce (12) |
+//! ciphertext+tag`, keyed with PBKDF2-HMAC-SHA1 over the password. Backups written by
+//! andOTP before 0.6.3 omit the iterations and salt and use SHA-256 of the password.
+
+use hmac::Hmac;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use super::{aes_gcm_open, invalid_backup, BackupEntry, Imported};
+use crate::error::Result;
+
+const SALT_LEN: usize = 12;
+const NONCE_LEN: usize = 12;
+/// PBKDF2 iteration counts taken as such. andOTP writes 140000 to 160000; anything
+/// else is the nonce of a pre-0.6.3 backup, or a crafted count that would take hours
+const ITERATIONS: std::ops::RangeInclusive<u32> = 1000..=1_000_000;
+
+#[derive(Deserialize)]
+struct Entry {
+    secret: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    label: String,
+    #[serde(rename = "type")]
+    kind: String,
+    algorithm: Option<String>,
+    digits: Option<u32>,
+    period: Option<u64>,
+    counter: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Reads an andOTP backup, prompting for the password if it is encrypted
+pub fn import(data: &[u8], password: &dyn Fn() -> Result<String>) -> Result<Imported> {
+    let entries: Vec<Entry> = match serde_json::from_slice(data) {
+        Ok(entries) => entries,
+        Err(_) => {
+            let plaintext = decrypt(data, &password()?)?;
+            serde_json::from_slice(&plaintext).map_err(invalid_backup)?
+        }
+    };
+
+    let mut imported = Imported::default();
+    for entry in entries {
+        imported.push(BackupEntry {
+            kind: &entry.kind,
+            issuer: &entry.issuer,
+            name: &entry.label,
+            secret: &entry.secret,
+            algorithm: entry.algorithm.as_deref().unwrap_or("SHA1"),
+            digits: entry.digits.unwrap_or(6),
+            period: entry.period.unwrap_or(30),
+            counter: entry.counter.unwrap_or(0),
+            note: None,
+            tags: entry.tags,
+        });
+    }
+    Ok(imported)
+}
+
+fn decrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
+    let iterations = match data.get(..4) {
+        Some(&[a, b, c, d]) => u32::from_be_bytes([a, b, c, d]),
+        _ => 0,
+    };
+    if data.len() > 4 + SALT_LEN + NONCE_LEN && ITERATIONS.contains(&iterations) {
+        let salt = &data[4..4 + SALT_LEN];
+        let rest = &data[4 + SALT_LEN..];
+
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha1>>(password.as_bytes(), salt, iterations, &mut key);
+        if let Ok(plaintext) = aes_gcm_open(&key, &rest[..NONCE_LEN], &rest[NONCE_LEN..]) {
+            return Ok(plaintext);
+        }
+    }
+
+    // Pre-PBKDF2 format
+    if data.len() <= NONCE_LEN {
+        return Err(invalid_backup(
+            "file is neither JSON nor an encrypted backup",
+        ));
+    }
+    let key = Sha256::digest(password.as_bytes());
+    aes_gcm_open(&key, &data[..NONCE_LEN], &data[NONCE_LEN..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::OtpKind;
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    const BACKUP: &str = r#"[
+        {"secret": "JBSWY3DPEHPK3PXP", "issuer": "GitHub", "label": "alice", "digits": 6,
+         "type": "TOTP", "algorithm": "SHA1", "period": 30, "tags": ["work"]},
+        {"secret": "JBSWY3DPEHPK3PXP", "issuer": "", "label": "VPN", "digits": 8,
+         "type": "HOTP", "algorithm": "SHA256", "counter": 12},
+        {"secret": "JBSWY3DPEHPK3PXP", "issuer": "Steam", "label": "me", "digits": 5,
+         "type": "STEAM", "algorithm": "SHA1", "period": 30}
+    ]"#;
+
+    fn encrypt(plaintext: &[u8], password: &str) -> Vec<u8> {
+        let iterations = 1000u32;
+        let salt = [7u8; SALT_LEN];
+        let nonce = [9u8; NONCE_LEN];
+
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha1>>(password.as_bytes(), &salt, iterations, &mut key);
+        let ciphertext = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .unwrap();
+
+        [&iterations.to_be_bytes()[..], &salt, &nonce, &ciphertext].concat()
+    }
+
+    fn assert_backup(imported: &Imported) {
+        assert_eq!(imported.accounts.len(), 2);
+        assert_eq!(imported.unsupported.len(), 1);
+        assert_eq!(imported.unsupported[0].0, "Steam:me");
+
+        let (name, github) = &imported.accounts[0];
+        assert_eq!(name, "GitHub:alice");
+        assert_eq!(github.tags, ["work"]);
+
+        let (name, vpn) = &imported.accounts[1];
+        assert_eq!(name, "VPN");
+        assert_eq!(vpn.kind, OtpKind::Hotp);
+        assert_eq!(vpn.counter, 12);
+        assert_eq!(vpn.digits, 8);
+    }
+
+    #[test]
+    fn imports_plain_backup() {
+        let imported = import(BACKUP.as_bytes(), &|| panic!("not encrypted")).unwrap();
+        assert_backup(&imported);
+    }
+
+    #[test]
+    fn imports_encrypted_backup() {
+        let data = encrypt(BACKUP.as_bytes(), "secret");
+        let imported = import(&data, &|| Ok("secret".to_string())).unwrap();
+        assert_backup(&imported);
+
+        assert!(import(&data, &|| Ok("wrong".to_string())).is_err());
+    }
+
+    #[test]
+    fn legacy_nonce_is_not_taken_for_an_iteration_count() {
+        let nonce = [0xff; NONCE_LEN];
+        let key = Sha256::digest(b"secret");
+        let ciphertext = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), BACKUP.as_bytes())
+            .unwrap();
+        let data = [&nonce[..], &ciphertext].concat();
+
+        let imported = import(&data, &|| Ok("secret".to_string())).unwrap();
+        assert_backup(&imported);
+    }
+}
@@ -0,0 +1,87 @@
+//! FreeOTP+ JSON export format
+//!
+//! Exports hold a `tokens` array whose secrets are arrays of signed bytes, as
+//! serialized from Java. FreeOTP+ exports are never encrypted.
+
+use serde::Deserialize;
+
+use super::{invalid_backup, BackupEntry, Imported};
+use crate::error::Result;
+
+#[derive(Deserialize)]
+struct Backup {
+    tokens: Vec<Token>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Token {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    issuer_ext: String,
+    #[serde(default)]
+    label: String,
+    secret: Vec<i8>,
+    algo: Option<String>,
+    digits: Option<u32>,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+/// Reads a FreeOTP+ JSON export
+pub fn import(data: &[u8]) -> Result<Imported> {
+    let backup: Backup = serde_json::from_slice(data).map_err(invalid_backup)?;
+
+    let mut imported = Imported::default();
+    for token in backup.tokens {
+        let secret: Vec<u8> = token.secret.iter().map(|&b| b as u8).collect();
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: true }, &secret);
+
+        imported.push(BackupEntry {
+            kind: &token.kind,
+            issuer: &token.issuer_ext,
+            name: &token.label,
+            secret: &secret,
+            algorithm: token.algo.as_deref().unwrap_or("SHA1"),
+            digits: token.digits.unwrap_or(6),
+            period: token.period.unwrap_or(30),
+            counter: token.counter.unwrap_or(0),
+            note: None,
+            tags: Vec::new(),
+        });
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::OtpKind;
+
+    #[test]
+    fn imports_signed_byte_secrets() {
+        // "Hello!\xde\xad\xbe\xef" as Java bytes
+        let backup = r#"{
+            "tokenOrder": ["GitHub:alice", "bob"],
+            "tokens": [
+                {"type": "TOTP", "issuerExt": "GitHub", "label": "alice", "algo": "SHA256",
+                 "digits": 6, "period": 30, "counter": 0,
+                 "secret": [72, 101, 108, 108, 111, 33, -34, -83, -66, -17]},
+                {"type": "HOTP", "issuerExt": "", "label": "bob", "algo": "SHA1",
+                 "digits": 6, "period": 30, "counter": 4,
+                 "secret": [72, 101, 108, 108, 111, 33, -34, -83, -66, -17]}
+            ]
+        }"#;
+        let imported = import(backup.as_bytes()).unwrap();
+
+        let (name, github) = &imported.accounts[0];
+        assert_eq!(name, "GitHub:alice");
+        assert_eq!(github.secret, "JBSWY3DPEHPK3PXP");
+
+        let (name, bob) = &imported.accounts[1];
+        assert_eq!(name, "bob");
+        assert_eq!(bob.kind, OtpKind::Hotp);
+        assert_eq!(bob.counter, 4);
+    }
+}
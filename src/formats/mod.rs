@@ -0,0 +1,218 @@
+//! Backup formats of other authenticator apps
+
+pub mod aegis;
+pub mod andotp;
+pub mod freeotp;
+pub mod twofas;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::account::{Account, OtpKind};
+use crate::error::{AuthError, Result};
+use crate::totp::{normalize_secret, split_label, OtpauthUri};
+
+/// Formats accounts can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Aegis,
+}
+
+impl FromStr for ExportFormat {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "aegis" => Ok(ExportFormat::Aegis),
+            _ => Err(AuthError::InvalidParameter(format!(
+                "unknown export format '{}', expected aegis",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Aegis => f.write_str("aegis"),
+        }
+    }
+}
+
+/// Formats accounts can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Aegis,
+    AndOtp,
+    TwoFas,
+    FreeOtp,
+}
+
+impl FromStr for ImportFormat {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "aegis" => Ok(ImportFormat::Aegis),
+            "andotp" => Ok(ImportFormat::AndOtp),
+            "2fas" => Ok(ImportFormat::TwoFas),
+            "freeotp" => Ok(ImportFormat::FreeOtp),
+            _ => Err(AuthError::InvalidParameter(format!(
+                "unknown import format '{}', expected aegis, andotp, 2fas or freeotp",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportFormat::Aegis => f.write_str("aegis"),
+            ImportFormat::AndOtp => f.write_str("andotp"),
+            ImportFormat::TwoFas => f.write_str("2fas"),
+            ImportFormat::FreeOtp => f.write_str("freeotp"),
+        }
+    }
+}
+
+/// Accounts read from a backup file
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub accounts: Vec<(String, Account)>,
+    /// Entries that cannot be represented, with the reason
+    pub unsupported: Vec<(String, String)>,
+}
+
+/// Reads a backup file; `password` is only called when the backup is encrypted
+pub fn import(
+    format: ImportFormat,
+    data: &[u8],
+    password: &dyn Fn() -> Result<String>,
+) -> Result<Imported> {
+    match format {
+        ImportFormat::Aegis => aegis::import(data, password),
+        ImportFormat::AndOtp => andotp::import(data, password),
+        ImportFormat::TwoFas => twofas::import(data, password),
+        ImportFormat::FreeOtp => freeotp::import(data),
+    }
+}
+
+/// Fields of one backup entry, in the shape most apps store them
+struct BackupEntry<'a> {
+    kind: &'a str,
+    issuer: &'a str,
+    name: &'a str,
+    secret: &'a str,
+    algorithm: &'a str,
+    digits: u32,
+    period: u64,
+    counter: u64,
+    note: Option<String>,
+    tags: Vec<String>,
+}
+
+impl Imported {
+    /// Converts an entry into an account named like imported otpauth URIs
+    fn push(&mut self, entry: BackupEntry) {
+        let (label_issuer, account) = split_label(entry.name);
+        let issuer = Some(entry.issuer.trim().to_string())
+            .filter(|i| !i.is_empty())
+            .or(label_issuer);
+        let display = match &issuer {
+            Some(issuer) => format!("{}:{}", issuer, account),
+            None => account.clone(),
+        };
+
+        let kind = match entry.kind.to_ascii_lowercase().as_str() {
+            "totp" => OtpKind::Totp,
+            "hotp" => OtpKind::Hotp,
+            other => {
+                self.unsupported
+                    .push((display, format!("{} tokens are not supported", other)));
+                return;
+            }
+        };
+
+        let parsed = normalize_secret(entry.secret).and_then(|secret| {
+            Ok(OtpauthUri {
+                kind,
+                account,
+                issuer,
+                secret,
+                algorithm: entry.algorithm.parse()?,
+                digits: entry.digits,
+                period: entry.period,
+                counter: entry.counter,
+            })
+        });
+
+        match parsed {
+            Ok(uri) => {
+                let account = Account {
+                    notes: entry.note.filter(|n| !n.is_empty()),
+                    tags: entry.tags,
+                    ..Account::from_uri(&uri)
+                };
+                self.accounts.push((uri.name(), account));
+            }
+            Err(e) => self.unsupported.push((display, e.to_string())),
+        }
+    }
+}
+
+/// Decrypts AES-256-GCM ciphertext with the tag appended, as produced by most apps
+fn aes_gcm_open(key: &[u8], nonce: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != 12 {
+        return Err(AuthError::Decryption("invalid nonce length".into()));
+    }
+    let cipher =
+        Aes256Gcm::new_from_slice(key).map_err(|e| AuthError::Decryption(e.to_string()))?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| AuthError::Decryption("wrong password or corrupted backup".into()))
+}
+
+fn invalid_backup(e: impl fmt::Display) -> AuthError {
+    AuthError::InvalidBackup(e.to_string())
+}
+
+/// Splits a stored account into the issuer and account name other apps keep separately
+fn issuer_and_name(name: &str, account: &Account) -> (String, String) {
+    match &account.issuer {
+        Some(issuer) => {
+            let name = name
+                .strip_prefix(issuer.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))
+                .unwrap_or(name);
+            (issuer.clone(), name.to_string())
+        }
+        None => {
+            let (issuer, name) = split_label(name);
+            (issuer.unwrap_or_default(), name)
+        }
+    }
+}
+
+/// Random version 4 UUID, used to identify exported entries and key slots
+fn random_uuid() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
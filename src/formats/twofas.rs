@@ -0,0 +1,152 @@
+//! 2FAS Authenticator backup format
+//!
+//! Backups are JSON with a `services` array. Encrypted backups leave it empty and
+//! store `servicesEncrypted` as `base64(ciphertext+tag):base64(salt):base64(nonce)`,
+//! AES-256-GCM keyed with PBKDF2-HMAC-SHA256 (10000 iterations) over the password.
+
+use base64::Engine;
+use hmac::Hmac;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use super::{aes_gcm_open, invalid_backup, BackupEntry, Imported};
+use crate::error::Result;
+
+const PBKDF2_ITERATIONS: u32 = 10_000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Backup {
+    #[serde(default)]
+    services: Vec<Service>,
+    services_encrypted: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Service {
+    #[serde(default)]
+    name: String,
+    secret: String,
+    #[serde(default)]
+    otp: Otp,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Otp {
+    account: Option<String>,
+    label: Option<String>,
+    issuer: Option<String>,
+    digits: Option<u32>,
+    period: Option<u64>,
+    algorithm: Option<String>,
+    counter: Option<u64>,
+    token_type: Option<String>,
+}
+
+/// Reads a 2FAS backup, prompting for the password if it is encrypted
+pub fn import(data: &[u8], password: &dyn Fn() -> Result<String>) -> Result<Imported> {
+    let backup: Backup = serde_json::from_slice(data).map_err(invalid_backup)?;
+
+    let services = match backup.services_encrypted {
+        Some(encrypted) if !encrypted.is_empty() => {
+            let plaintext = decrypt(&encrypted, &password()?)?;
+            serde_json::from_slice(&plaintext).map_err(invalid_backup)?
+        }
+        _ => backup.services,
+    };
+
+    let mut imported = Imported::default();
+    for service in &services {
+        let otp = &service.otp;
+        imported.push(BackupEntry {
+            kind: otp.token_type.as_deref().unwrap_or("TOTP"),
+            issuer: otp.issuer.as_deref().unwrap_or(&service.name),
+            name: otp
+                .account
+                .as_deref()
+                .or(otp.label.as_deref())
+                .unwrap_or(&service.name),
+            secret: &service.secret,
+            algorithm: otp.algorithm.as_deref().unwrap_or("SHA1"),
+            digits: otp.digits.unwrap_or(6),
+            period: otp.period.unwrap_or(30),
+            counter: otp.counter.unwrap_or(0),
+            note: None,
+            tags: Vec::new(),
+        });
+    }
+    Ok(imported)
+}
+
+fn decrypt(encrypted: &str, password: &str) -> Result<Vec<u8>> {
+    let parts: Vec<&str> = encrypted.split(':').collect();
+    let [sealed, salt, nonce] = parts[..] else {
+        return Err(invalid_backup("servicesEncrypted has an unexpected layout"));
+    };
+
+    let decode = |part: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(part)
+            .map_err(invalid_backup)
+    };
+    let (sealed, salt, nonce) = (decode(sealed)?, decode(salt)?, decode(nonce)?);
+
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, PBKDF2_ITERATIONS, &mut key);
+    aes_gcm_open(&key, &nonce, &sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    const SERVICES: &str = r#"[
+        {"name": "GitHub", "secret": "JBSWY3DPEHPK3PXP",
+         "otp": {"account": "alice", "issuer": "GitHub", "digits": 6, "period": 30,
+                 "algorithm": "SHA1", "tokenType": "TOTP"}},
+        {"name": "Steam", "secret": "JBSWY3DPEHPK3PXP",
+         "otp": {"account": "me", "digits": 5, "tokenType": "STEAM"}}
+    ]"#;
+
+    fn assert_services(imported: &Imported) {
+        assert_eq!(imported.accounts.len(), 1);
+        assert_eq!(imported.accounts[0].0, "GitHub:alice");
+        assert_eq!(imported.unsupported.len(), 1);
+        assert_eq!(imported.unsupported[0].0, "Steam:me");
+    }
+
+    #[test]
+    fn imports_plain_backup() {
+        let backup = format!(r#"{{"services": {}, "schemaVersion": 4}}"#, SERVICES);
+        let imported = import(backup.as_bytes(), &|| panic!("not encrypted")).unwrap();
+        assert_services(&imported);
+    }
+
+    #[test]
+    fn imports_encrypted_backup() {
+        let salt = [3u8; 256];
+        let nonce = [5u8; 12];
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(b"secret", &salt, PBKDF2_ITERATIONS, &mut key);
+        let sealed = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), SERVICES.as_bytes())
+            .unwrap();
+
+        let b64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+        let backup = serde_json::json!({
+            "services": [],
+            "servicesEncrypted": format!("{}:{}:{}", b64(&sealed), b64(&salt), b64(&nonce)),
+            "schemaVersion": 4,
+        })
+        .to_string();
+
+        let imported = import(backup.as_bytes(), &|| Ok("secret".to_string())).unwrap();
+        assert_services(&imported);
+
+        assert!(import(backup.as_bytes(), &|| Ok("wrong".to_string())).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! R-Auth: A TOTP Authentication Implementation
+//!
+//! This crate implements the following RFCs:
+//! - [RFC 6238](https://datatracker.ietf.org/doc/html/rfc6238) - TOTP: Time-Based One-Time Password Algorithm
+//! - [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226) - HOTP: HMAC-Based One-Time Password Algorithm
+//! - [RFC 4648](https://datatracker.ietf.org/doc/html/rfc4648) - Base-N Encodings
+//!
+//! The library never prints or prompts on its own, with the one exception of
+//! [`crypto::PassphraseSource::Prompt`], which asks for a vault or SSH key passphrase
+//! on the terminal when explicitly chosen.
+//!
+//! # Generating and verifying codes
+//!
+//! ```
+//! use r_auth::totp::TOTP;
+//!
+//! let totp = TOTP::new("JBSWY3DPEHPK3PXP")?;
+//! let code = totp.generate(1_700_000_000)?;
+//! assert_eq!(totp.verify(&code, 1_700_000_035, 1)?, Some(-1));
+//! # Ok::<(), r_auth::error::AuthError>(())
+//! ```
+//!
+//! # Parsing otpauth URIs
+//!
+//! ```
+//! use r_auth::account::Account;
+//! use r_auth::totp::OtpauthUri;
+//!
+//! let uri = OtpauthUri::parse("otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub")?;
+//! assert_eq!(uri.name(), "GitHub:alice");
+//! let account = Account::from_uri(&uri);
+//! # Ok::<(), r_auth::error::AuthError>(())
+//! ```
+//!
+//! # Opening an encrypted vault
+//!
+//! [`authenticator::TOTPAuthenticator`] keeps accounts in an age-encrypted file. Every
+//! change is saved atomically under a lock shared with other processes.
+//! The key comes from the [`crypto::Crypto`] it is given, which can also read it from
+//! any [`crypto::KeyStore`]: the system keyring, a passphrase-protected file or memory.
+//!
+//! ```
+//! use age::x25519::Identity;
+//! use r_auth::account::AccountParams;
+//! use r_auth::authenticator::TOTPAuthenticator;
+//! use r_auth::crypto::Crypto;
+//!
+//! # let dir = std::env::temp_dir().join(format!("r-auth-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&dir)?;
+//! let crypto = Crypto::from_identity(Identity::generate());
+//! let mut vault = TOTPAuthenticator::open(&dir.join("accounts.json"), crypto)?;
+//!
+//! vault.add_account("GitHub", Some("JBSWY3DPEHPK3PXP"), AccountParams::default(), false)?;
+//! let code = vault.get_code("GitHub")?.expect("account was just added");
+//! println!("{} ({}s left)", code.code, code.remaining_seconds.unwrap_or_default());
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod account;
+pub mod authenticator;
+pub mod backup;
+pub mod crypto;
+pub mod error;
+pub mod formats;
+pub mod hotp;
+pub mod matcher;
+pub mod migration;
+pub mod recipient;
+mod storage;
+pub mod totp;
+pub mod vault;
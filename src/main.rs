@@ -1,19 +1,30 @@
-//! R-Auth: A TOTP Authentication Implementation
-//!
-//! This crate implements the following RFCs:
-//! - [RFC 6238](https://datatracker.ietf.org/doc/html/rfc6238) - TOTP: Time-Based One-Time Password Algorithm
-//! - [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226) - HOTP: HMAC-Based One-Time Password Algorithm
-//! - [RFC 4648](https://datatracker.ietf.org/doc/html/rfc4648) - Base-N Encodings
+//! Command-line interface of R-Auth, built on the `r_auth` library
 
 use clap::{Parser, Subcommand};
+use r_auth::{authenticator, crypto, formats, matcher, migration, recipient};
+use serde_json::json;
 
-mod authenticator;
-mod crypto;
-mod error;
-mod totp;
-use std::io::{stdin, stdout, Write};
+mod clipboard;
+mod config;
+mod output;
+mod tui;
+use std::io::{stderr, stdin, BufRead, BufReader, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::error::{AuthError, Result};
+use crate::clipboard::ClipboardBackend;
+use crate::config::{Config, Key, Source};
+use crate::output::{AccountInfo, CodeInfo, OutputFormat};
+use r_auth::account::{Account, AccountParams, OtpKind};
+use r_auth::authenticator::{ImportReport, OnConflict, DEFAULT_LOCK_TIMEOUT};
+use r_auth::backup::{Backup, Diff, KeySetupDiff, RestoreMode, VaultInfo};
+use r_auth::crypto::{KeyMode, PassphraseSource};
+use r_auth::error::{AuthError, Result};
+use r_auth::formats::{ExportFormat, ImportFormat};
+use r_auth::matcher::{MatchKind, Resolution};
+use r_auth::recipient::Recipient;
+use r_auth::totp::{Algorithm, OtpauthUri};
+use r_auth::vault::Vaults;
 
 #[derive(Parser)]
 #[command(
@@ -26,12 +37,65 @@ use crate::error::{AuthError, Result};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Read the vault passphrase from this file descriptor instead of prompting
+    #[arg(long, global = true, value_name = "FD")]
+    passphrase_fd: Option<i32>,
+    /// Seconds to wait for another r-auth process to release the accounts database
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = DEFAULT_LOCK_TIMEOUT.as_secs())]
+    lock_timeout: u64,
+    /// Output format: human-readable text or JSON for scripts
+    ///
+    /// In JSON mode each command prints a single JSON document on stdout. Failures
+    /// print {"error": {"kind", "message", "exit_code"}} instead, and the process
+    /// exits with a code specific to the error kind.
+    #[arg(long, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Use the accounts database at this path, encrypted with the shared key
+    #[arg(long, global = true, value_name = "PATH", env = "R_AUTH_VAULT")]
+    vault: Option<PathBuf>,
+    /// Use a named vault created with `r-auth vault create`
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        env = "R_AUTH_PROFILE",
+        conflicts_with = "vault"
+    )]
+    profile: Option<String>,
+    /// Override a setting for this run, see `r-auth config`
+    #[arg(long = "set", global = true, value_name = "SETTING=VALUE")]
+    set: Vec<String>,
+    /// Also decrypt with this age identity file or SSH private key, to read a vault
+    /// shared with it; see `r-auth recipient`
+    #[arg(long, global = true, value_name = "FILE")]
+    identity: Vec<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize the authenticator with a new encryption key
-    Init,
+    ///
+    /// By default the key is stored in the system keyring. With --passphrase the key is
+    /// instead stored in the config directory, encrypted with a passphrase, for hosts
+    /// without a keyring such as servers, containers and CI runners.
+    ///
+    /// With --key-file no key is generated: the vault is encrypted to an age identity
+    /// file or SSH ed25519 or RSA private key you already have, which is read each time
+    /// and never changed. A passphrase-protected SSH key is unlocked as needed.
+    ///
+    /// Examples:
+    ///   r-auth init                                  # key in system keyring
+    ///   r-auth init --passphrase                     # prompt for a passphrase
+    ///   r-auth init --passphrase --passphrase-fd 3 3<passphrase.txt
+    ///   r-auth init --key-file ~/.ssh/id_ed25519     # use an existing SSH key
+    Init {
+        /// Protect the key with a passphrase instead of the system keyring
+        #[arg(long, conflicts_with = "key_file")]
+        passphrase: bool,
+        /// Use this age identity file or SSH private key as the key
+        #[arg(long, value_name = "FILE")]
+        key_file: Option<PathBuf>,
+    },
     /// Add a new account
     ///
     /// This command adds a new TOTP account to the authenticator. If no secret is provided,
@@ -43,6 +107,9 @@ enum Commands {
     ///   r-auth add "GitHub" JBSWY3DPEHPK3PXP               # secret as positional argument
     ///   r-auth add "GitHub" --secret JBSWY3DPEHPK3PXP      # secret with flag
     ///   r-auth add "GitHub" --force                        # replace existing account
+    ///   r-auth add "AWS" --secret JBSWY3DPEHPK3PXP --algorithm SHA256
+    ///   r-auth add "VPN" --secret JBSWY3DPEHPK3PXP --digits 8 --period 60
+    ///   r-auth add "Token" --secret JBSWY3DPEHPK3PXP --type hotp --counter 0
     #[command(arg_required_else_help = true)]
     Add {
         /// Name of the account
@@ -53,6 +120,27 @@ enum Commands {
         /// Optional secret key (with flag)
         #[arg(long, conflicts_with = "secret_pos")]
         secret: Option<String>,
+        /// Account type: time-based (totp) or counter-based (hotp)
+        #[arg(long = "type", default_value_t = OtpKind::Totp)]
+        kind: OtpKind,
+        /// HMAC algorithm used to generate codes (SHA1, SHA256 or SHA512) [default: algorithm setting]
+        #[arg(long)]
+        algorithm: Option<Algorithm>,
+        /// Number of digits in generated codes (6-8) [default: digits setting]
+        #[arg(long)]
+        digits: Option<u32>,
+        /// Time step in seconds between codes (TOTP only) [default: period setting]
+        #[arg(long)]
+        period: Option<u64>,
+        /// Initial counter value (HOTP only)
+        #[arg(long, default_value_t = 0)]
+        counter: u64,
+        /// Issuer shown by authenticator apps scanning the QR code [default: issuer setting]
+        #[arg(long)]
+        issuer: Option<String>,
+        /// Do not print the QR code [default: show_qr setting]
+        #[arg(long)]
+        no_qr: bool,
         /// Force add even if account exists
         #[arg(long, short)]
         force: bool,
@@ -61,6 +149,8 @@ enum Commands {
     ///
     /// This command removes an existing TOTP account from the authenticator.
     /// The account name can be specified either as a positional argument or using the --name flag.
+    /// Partial names are matched as for `code`, and the matched account is always confirmed;
+    /// exact names are confirmed unless the confirm setting is off.
     ///
     /// Examples:
     ///   r-auth remove "Google Account"        # name as positional argument
@@ -77,15 +167,31 @@ enum Commands {
     /// List all accounts
     List,
     /// Show live TOTP codes
-    Show,
+    ///
+    /// Opens an interactive view with a bar showing how long each code stays valid.
+    /// Move with the arrow keys or j/k, press / to search, Enter to copy the selected
+    /// code and q to quit.
+    Show {
+        /// Seconds before a copied code is cleared from the clipboard (0 keeps it) [default: clear_after setting]
+        #[arg(long, value_name = "SECONDS")]
+        clear_after: Option<u64>,
+        /// Clipboard to copy to (auto, wayland, xclip, xsel, pbcopy or osc52)
+        #[arg(long, default_value_t = ClipboardBackend::Auto)]
+        clipboard: ClipboardBackend,
+    },
     /// Get code for a specific account
     ///
     /// This command displays the current TOTP code for a specified account.
     /// The account name can be specified either as a positional argument or using the --name flag.
+    /// Part of the name is enough: it is matched ignoring case as a prefix, a substring or
+    /// letters in order, and on a terminal you pick from the candidates when several match.
     ///
     /// Examples:
     ///   r-auth code "Google Account"        # name as positional argument
     ///   r-auth code --name "Google Account" # name with flag
+    ///   r-auth code "Google Account" --copy # copy to the clipboard
+    ///   r-auth code "Google Account" --next # also show expiry and the next code
+    ///   r-auth code "Google Account" --wait-fresh 10 # never print a code about to expire
     #[command(arg_required_else_help = true)]
     Code {
         /// Name of the account (positional)
@@ -94,14 +200,400 @@ enum Commands {
         /// Name of the account (with flag)
         #[arg(long, conflicts_with = "name_pos")]
         name: Option<String>,
+        /// Copy the code to the clipboard
+        #[arg(long, short)]
+        copy: bool,
+        /// Seconds before the copied code is cleared from the clipboard (0 keeps it) [default: clear_after setting]
+        ///
+        /// The clipboard is only cleared if it still holds the code, and only for
+        /// clipboards that can be read back, which excludes osc52.
+        #[arg(long, value_name = "SECONDS")]
+        clear_after: Option<u64>,
+        /// Clipboard to copy to (auto, wayland, xclip, xsel, pbcopy or osc52)
+        #[arg(long, default_value_t = ClipboardBackend::Auto)]
+        clipboard: ClipboardBackend,
+        /// Also show how long the code stays valid and the code that follows it
+        #[arg(long)]
+        next: bool,
+        /// Also show the code of the previous time step
+        #[arg(long)]
+        previous: bool,
+        /// Wait until the code stays valid for at least this many seconds (TOTP only)
+        #[arg(long, value_name = "SECONDS")]
+        wait_fresh: Option<u64>,
     },
+    /// Import accounts from otpauth:// URIs
+    ///
+    /// URIs are taken from the arguments, from a file with one URI per line, or from
+    /// stdin when neither is given. Blank lines and lines starting with '#' are ignored.
+    /// Accounts are named "Issuer:account" when the URI carries an issuer.
+    ///
+    /// Google Authenticator exports (otpauth-migration://offline?data=...) are also
+    /// accepted. Every batch of a multi-QR export must be given; if any batch is
+    /// missing or malformed nothing is imported.
+    ///
+    /// Examples:
+    ///   r-auth import-uri "otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub"
+    ///   r-auth import-uri --file uris.txt --on-conflict rename
+    ///   zbarimg -q --raw qr.png | r-auth import-uri
+    ImportUri {
+        /// One or more otpauth:// URIs
+        #[arg(conflicts_with = "file")]
+        uris: Vec<String>,
+        /// Read URIs from a file, one per line
+        #[arg(long, short)]
+        file: Option<PathBuf>,
+        /// What to do when an account with the same name exists (skip, replace or rename)
+        #[arg(long, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+    },
+    /// Import accounts from another authenticator app's backup file
+    ///
+    /// Supported formats are aegis, andotp and 2fas (plain or encrypted backups) and
+    /// freeotp (FreeOTP+ JSON export). You are prompted for the password when the
+    /// backup is encrypted. Token types r-auth cannot generate, such as Steam or
+    /// Yandex, are reported and skipped.
+    ///
+    /// Examples:
+    ///   r-auth import --format aegis aegis-backup.json
+    ///   r-auth import --format andotp otp_accounts.json.aes --on-conflict rename
+    #[command(arg_required_else_help = true)]
+    Import {
+        /// Backup file to read
+        file: PathBuf,
+        /// Backup format (aegis, andotp, 2fas or freeotp)
+        #[arg(long)]
+        format: ImportFormat,
+        /// What to do when an account with the same name exists (skip, replace or rename)
+        #[arg(long, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+    },
+    /// Export all accounts to another authenticator app's format
+    ///
+    /// The export contains every secret. Without --encrypt it is written in plain text,
+    /// so prefer --encrypt and keep the file somewhere safe.
+    ///
+    /// Examples:
+    ///   r-auth export --format aegis --encrypt --file aegis.json
+    ///   r-auth export --format aegis > aegis-plain.json
+    Export {
+        /// Target format (aegis)
+        #[arg(long, default_value_t = ExportFormat::Aegis)]
+        format: ExportFormat,
+        /// Write to a file instead of stdout
+        #[arg(long, short)]
+        file: Option<PathBuf>,
+        /// Protect the export with a password
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Write an encrypted backup of the vault to a file
+    ///
+    /// The backup holds every account and the vault's settings, such as who it is
+    /// shared with. It is encrypted with a passphrase of its own rather than the vault's
+    /// key, so it can be restored with `r-auth restore` on another machine or after a
+    /// reset. Keep it somewhere other than this machine.
+    ///
+    /// Examples:
+    ///   r-auth backup --to ~/sync/r-auth.age
+    ///   r-auth --profile work backup --to work.age
+    #[command(arg_required_else_help = true)]
+    Backup {
+        /// File to write the backup to
+        #[arg(long, value_name = "FILE")]
+        to: PathBuf,
+    },
+    /// Restore accounts from a backup made with `r-auth backup`
+    ///
+    /// The backup is checked and the changes it would make are listed before anything
+    /// is written. Its accounts are merged into the vault, settling accounts that differ
+    /// as --on-conflict says; with --replace the vault ends up with exactly the accounts
+    /// of the backup. Public keys the vault was shared with are added back as recipients.
+    ///
+    /// Examples:
+    ///   r-auth init && r-auth restore --from ~/sync/r-auth.age
+    ///   r-auth restore --from r-auth.age --on-conflict replace
+    ///   r-auth restore --from r-auth.age --replace --dry-run
+    #[command(arg_required_else_help = true)]
+    Restore {
+        /// Backup file to read
+        #[arg(long, value_name = "FILE")]
+        from: PathBuf,
+        /// What to do with accounts that differ from the backup (skip, replace or rename)
+        #[arg(long, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+        /// Remove the accounts that are not in the backup and replace those that differ
+        #[arg(long, conflicts_with = "on_conflict")]
+        replace: bool,
+        /// Only list the changes
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Resynchronize the counter of an HOTP account
+    ///
+    /// Searches ahead of the stored counter for two consecutive codes produced by the
+    /// token and stores the counter that follows them.
+    ///
+    /// Examples:
+    ///   r-auth resync "VPN" 162583 399871
+    ///   r-auth resync "VPN" 162583 399871 --window 500
+    #[command(arg_required_else_help = true)]
+    Resync {
+        /// Name of the account
+        name: String,
+        /// A code produced by the token
+        first: String,
+        /// The code produced immediately after the first one
+        second: String,
+        /// Number of counter values to search ahead
+        #[arg(long, default_value_t = 100)]
+        window: u64,
+    },
+    /// Check a code against a TOTP account
+    ///
+    /// Exits with status 0 when the code is valid and 37 when it is rejected. Codes up
+    /// to --skew time steps early or late are accepted to allow for clock drift.
+    /// With --record the accepted time step is saved and codes from that step or
+    /// earlier are rejected from then on, so a code can only be used once.
+    ///
+    /// Examples:
+    ///   r-auth verify "GitHub" 123456
+    ///   r-auth verify "GitHub" 123456 --skew 2 --record
+    #[command(arg_required_else_help = true)]
+    Verify {
+        /// Name of the account
+        name: String,
+        /// The code to check
+        code: String,
+        /// Number of time steps before and after the current one to accept
+        #[arg(long, default_value_t = 1)]
+        skew: u32,
+        /// Remember the accepted code to reject replays
+        #[arg(long)]
+        record: bool,
+    },
+    /// Restore the accounts database from its backup
+    ///
+    /// Every save keeps the previous version of accounts.json as accounts.json.bak.
+    /// If accounts.json can no longer be decrypted, for example after a crash or a full
+    /// disk, this restores the backup and keeps the damaged file as accounts.json.corrupt.
+    Recover,
+    /// Encrypt the vault with a newly generated key (use if the key may have leaked)
+    ///
+    /// Every named vault sharing the key is re-encrypted too. The vaults are saved
+    /// before the stored key is replaced, and put back as they were if any step fails.
+    /// A passphrase-protected key keeps its passphrase.
+    ///
+    /// Examples:
+    ///   r-auth rekey
+    ///   r-auth rekey --include ~/backup/accounts.json  # another vault using the key
+    Rekey {
+        /// Another vault file encrypted with the same key, such as one used with --vault
+        #[arg(long, value_name = "PATH")]
+        include: Vec<PathBuf>,
+    },
+    /// Share the vault by encrypting it to other people's public keys
+    ///
+    /// A vault is normally encrypted to its key alone. Each recipient added - an age
+    /// public key (age1...) or an SSH public key (ssh-ed25519 or ssh-rsa) - can then
+    /// read and change it with --identity pointing at the matching private key. The
+    /// list is kept in plain text next to the vault, in a .recipients file, and the
+    /// vault's own key is added to it with the first recipient.
+    ///
+    /// A removed recipient can no longer read the vault, but keeps any secret they
+    /// have seen: rotate the secrets that matter.
+    ///
+    /// Examples:
+    ///   r-auth --vault team.json recipient add "$(cat alice.pub)" age1...
+    ///   r-auth --vault team.json recipient add --file oncall.txt
+    ///   r-auth --vault team.json --identity ~/.ssh/id_ed25519 code aws
+    ///   r-auth --vault team.json recipient remove alice@laptop
+    #[command(subcommand)]
+    Recipient(RecipientCommand),
     /// Reset everything - removes encryption key and all accounts (dangerous!)
+    ///
+    /// With --profile or --vault only that vault is reset. The key is removed only
+    /// when it belongs to the vault: the default vault's key, or a named vault's own.
     Reset,
+    /// Manage named vaults
+    ///
+    /// Named vaults keep separate sets of accounts, for example for work and personal
+    /// use, each in its own file. Select one for any command with --profile NAME or
+    /// R_AUTH_PROFILE. A vault shares the default vault's key unless it is created
+    /// with --own-key.
+    ///
+    /// Examples:
+    ///   r-auth vault create work --own-key
+    ///   r-auth --profile work add "GitHub" JBSWY3DPEHPK3PXP
+    ///   R_AUTH_PROFILE=work r-auth code github
+    ///   r-auth vault list
+    #[command(subcommand)]
+    Vault(VaultCommand),
+    /// Show or change settings
+    ///
+    /// Settings are read from config.toml in the r-auth config directory, then from
+    /// R_AUTH_<SETTING> environment variables, then from --set SETTING=VALUE, each
+    /// overriding the one before. Flags of individual commands, such as add --digits,
+    /// override them all.
+    ///
+    /// Settings: issuer, algorithm, digits, period, show_qr, confirm (ask before
+    /// replacing or removing an account) and clear_after.
+    ///
+    /// Examples:
+    ///   r-auth config show
+    ///   r-auth config set digits 8
+    ///   R_AUTH_CONFIRM=false r-auth config get confirm
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Clears a copied code from the clipboard after a delay; started by `code --copy`
+    #[command(hide = true)]
+    ClearClipboard {
+        #[arg(long)]
+        after: u64,
+        #[arg(long)]
+        clipboard: ClipboardBackend,
+    },
+}
+
+#[derive(Subcommand)]
+enum VaultCommand {
+    /// List the default vault and all named vaults
+    List,
+    /// Create a named vault
+    #[command(arg_required_else_help = true)]
+    Create {
+        /// Name of the vault: letters, digits, '-' and '_'
+        name: String,
+        /// Encrypt the vault with a key of its own instead of the shared key
+        #[arg(long)]
+        own_key: bool,
+        /// Protect the vault's own key with a passphrase instead of the system keyring
+        #[arg(long, requires = "own_key", conflicts_with = "key_file")]
+        passphrase: bool,
+        /// Use this age identity file or SSH private key as the vault's own key
+        #[arg(long, value_name = "FILE", requires = "own_key")]
+        key_file: Option<PathBuf>,
+    },
+    /// Delete a named vault, its accounts and its own key (dangerous!)
+    #[command(arg_required_else_help = true)]
+    Delete {
+        /// Name of the vault
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RecipientCommand {
+    /// List the public keys the vault is encrypted to
+    List,
+    /// Add recipients and re-encrypt the vault
+    #[command(arg_required_else_help = true)]
+    Add {
+        /// Public keys to add
+        keys: Vec<String>,
+        /// Read public keys from a file, one per line, such as an authorized_keys file
+        #[arg(long, short)]
+        file: Vec<PathBuf>,
+    },
+    /// Remove recipients and re-encrypt the vault
+    #[command(arg_required_else_help = true)]
+    Remove {
+        /// Public keys to remove, or the comments of SSH keys such as alice@laptop
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print every setting with its effective value and where it came from
+    Show,
+    /// Print the effective value of a setting and where it came from
+    #[command(arg_required_else_help = true)]
+    Get {
+        /// Name of the setting
+        key: Key,
+    },
+    /// Store a setting in the config file
+    #[command(arg_required_else_help = true)]
+    Set {
+        /// Name of the setting
+        key: Key,
+        /// New value
+        value: String,
+    },
+    /// Remove a setting from the config file, restoring its default
+    #[command(arg_required_else_help = true)]
+    Unset {
+        /// Name of the setting
+        key: Key,
+    },
+}
+
+impl Commands {
+    /// Commands that repair, rotate or move a vault, which still run with default
+    /// settings when the config cannot be read
+    fn is_recovery(&self) -> bool {
+        matches!(
+            self,
+            Commands::Recover
+                | Commands::Reset
+                | Commands::Rekey { .. }
+                | Commands::Backup { .. }
+                | Commands::Restore { .. }
+        )
+    }
+}
+
+/// Resolves a possibly partial account name; see [`matcher::resolve`]
+/// When several accounts match and `interactive` is set, the user picks one on the terminal
+fn resolve_account(
+    authenticator: &authenticator::TOTPAuthenticator,
+    query: &str,
+    interactive: bool,
+) -> Result<(String, MatchKind)> {
+    let names = authenticator.accounts().keys().map(String::as_str);
+    match matcher::resolve(query, names) {
+        Resolution::Unique(name, kind) => Ok((name, kind)),
+        Resolution::NotFound => Err(AuthError::AccountNotFound(query.to_string())),
+        Resolution::Ambiguous(candidates, kind) => {
+            if interactive && stdin().is_terminal() && stderr().is_terminal() {
+                if let Some(name) = pick_account(query, &candidates)? {
+                    return Ok((name, kind));
+                }
+            }
+            Err(AuthError::AmbiguousAccount(query.to_string(), candidates))
+        }
+    }
+}
+
+fn pick_account(query: &str, candidates: &[String]) -> Result<Option<String>> {
+    eprintln!("'{}' matches several accounts:", query);
+    for (i, name) in candidates.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, name);
+    }
+    eprint!("Select an account [1-{}]: ", candidates.len());
+    stderr().flush()?;
+
+    let mut input = String::new();
+    stdin().read_line(&mut input)?;
+    Ok(input
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| candidates.get(n.checked_sub(1)?))
+        .cloned())
+}
+
+/// Whether removing an account matched as `kind` must be confirmed first. Only a
+/// byte-identical name may skip the prompt, and only when confirmations are off
+fn remove_needs_confirmation(kind: MatchKind, always_confirm: bool) -> bool {
+    always_confirm || kind != MatchKind::Exact
 }
 
 fn confirm(prompt: &str) -> bool {
-    print!("{} (y/N): ", prompt);
-    stdout().flush().unwrap();
+    // Prompt on stderr so stdout stays parseable
+    eprint!("{} (y/N): ", prompt);
+    stderr().flush().unwrap();
 
     let mut input = String::new();
     stdin().read_line(&mut input).unwrap();
@@ -109,46 +601,737 @@ fn confirm(prompt: &str) -> bool {
     input.trim().eq_ignore_ascii_case("y")
 }
 
+/// Prompts twice for a new password without echoing it
+fn prompt_new_password(prompt: &str) -> Result<String> {
+    let password = rpassword::prompt_password(format!("{}: ", prompt))?;
+    if password.is_empty() {
+        return Err(AuthError::InvalidParameter(
+            "Password cannot be empty".into(),
+        ));
+    }
+    let confirmation = rpassword::prompt_password("Confirm password: ")?;
+    if password != confirmation {
+        return Err(AuthError::InvalidParameter("Passwords do not match".into()));
+    }
+    Ok(password)
+}
+
+/// Writes a file readable only by the current user
+fn write_private(path: &PathBuf, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+/// Reads otpauth URIs from the arguments, a file or stdin, labelled by their position
+fn read_uris(uris: Vec<String>, file: Option<PathBuf>) -> Result<Vec<(String, String)>> {
+    if !uris.is_empty() {
+        return Ok(uris
+            .into_iter()
+            .enumerate()
+            .map(|(i, uri)| (format!("argument {}", i + 1), uri))
+            .collect());
+    }
+
+    let reader: Box<dyn BufRead> = match file {
+        Some(path) => Box::new(BufReader::new(std::fs::File::open(path)?)),
+        None => Box::new(stdin().lock()),
+    };
+
+    let mut lines = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            lines.push((format!("line {}", i + 1), line.to_string()));
+        }
+    }
+    Ok(lines)
+}
+
+fn print_import_report(
+    report: &ImportReport,
+    unsupported: &[(String, String)],
+    invalid: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    if format == OutputFormat::Json {
+        return output::print_json(&json!({
+            "added": report.added,
+            "replaced": report.replaced,
+            "skipped": report.skipped,
+            "failed": report
+                .failed
+                .iter()
+                .map(|(name, e)| json!({"name": name, "kind": e.kind(), "message": e.to_string()}))
+                .collect::<Vec<_>>(),
+            "unsupported": unsupported
+                .iter()
+                .map(|(name, reason)| json!({"name": name, "reason": reason}))
+                .collect::<Vec<_>>(),
+            "invalid": invalid,
+        }));
+    }
+
+    for (name, reason) in unsupported {
+        eprintln!("Unsupported '{}': {}", name, reason);
+    }
+    for name in &report.added {
+        println!("Added '{}'", name);
+    }
+    for name in &report.replaced {
+        println!("Replaced '{}'", name);
+    }
+    for name in &report.skipped {
+        println!("Skipped '{}': account already exists", name);
+    }
+    for (name, error) in &report.failed {
+        eprintln!("Failed '{}': {}", name, error);
+    }
+    println!(
+        "\nImported {} account(s): {} added, {} replaced, {} skipped, {} unsupported, {} failed",
+        report.added.len() + report.replaced.len(),
+        report.added.len(),
+        report.replaced.len(),
+        report.skipped.len(),
+        unsupported.len(),
+        report.failed.len()
+    );
+    if invalid > 0 {
+        println!("{} URI(s) could not be parsed", invalid);
+    }
+    Ok(())
+}
+
+/// Lists what restoring `backup` would change, before asking
+fn print_restore_preview(
+    backup: &Backup,
+    diff: &Diff,
+    key_setup: &KeySetupDiff,
+    mode: RestoreMode,
+    recipients: usize,
+) {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs().saturating_sub(backup.created_at) / 86400)
+        .unwrap_or_default();
+    let made = match days {
+        0 => "today".to_string(),
+        1 => "1 day ago".to_string(),
+        days => format!("{} days ago", days),
+    };
+    let source = match &backup.vault.name {
+        Some(name) => format!(" of vault '{}'", name),
+        None => String::new(),
+    };
+    println!(
+        "Backup{} made {}, with {} account(s)",
+        source,
+        made,
+        backup.accounts.len()
+    );
+
+    let changed = match mode {
+        RestoreMode::Merge(OnConflict::Skip) => "differs, kept as it is",
+        RestoreMode::Merge(OnConflict::Rename) => "differs, the backup's is added as a copy",
+        RestoreMode::Merge(OnConflict::Replace) | RestoreMode::Replace => "replaced",
+    };
+    for name in &diff.added {
+        println!("  + {} (added)", name);
+    }
+    for name in &diff.changed {
+        println!("  ~ {} ({})", name, changed);
+    }
+    if mode == RestoreMode::Replace {
+        for name in &diff.missing {
+            println!("  - {} (removed)", name);
+        }
+    }
+    if !diff.unchanged.is_empty() {
+        println!("  {} account(s) unchanged", diff.unchanged.len());
+    }
+    if recipients > 0 {
+        println!(
+            "  {} recipient(s) of the backup added to the vault",
+            recipients
+        );
+    }
+
+    let key = |own_key: bool| {
+        if own_key {
+            "its own key"
+        } else {
+            "the shared key"
+        }
+    };
+    let key_file = |key_file: &Option<PathBuf>| match key_file {
+        Some(path) => path.display().to_string(),
+        None => "a generated key".to_string(),
+    };
+    if let Some(own_key) = &key_setup.own_key {
+        println!(
+            "  ! the backed up vault used {}, this one uses {}",
+            key(own_key.backup),
+            key(own_key.vault)
+        );
+    }
+    if let Some(change) = &key_setup.key_file {
+        println!(
+            "  ! the backed up vault was encrypted to {}, this one to {}",
+            key_file(&change.backup),
+            key_file(&change.vault)
+        );
+    }
+    if !key_setup.is_empty() {
+        println!("  The vault's key is kept as it is; set it up again by hand if needed");
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
+    let format = cli.output;
     if let Err(e) = run(cli) {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+        match format {
+            OutputFormat::Text => eprintln!("Error: {}", e),
+            OutputFormat::Json => {
+                let _ = output::print_json(&output::error_json(&e));
+            }
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn key_mode(passphrase: bool, key_file: Option<PathBuf>) -> KeyMode {
+    match key_file {
+        Some(path) => KeyMode::KeyFile(path),
+        None if passphrase => KeyMode::Passphrase,
+        None => KeyMode::Keyring,
+    }
+}
+
+fn run_vault(
+    vaults: &Vaults,
+    command: VaultCommand,
+    passphrase_source: PassphraseSource,
+    format: OutputFormat,
+) -> Result<()> {
+    let json = format == OutputFormat::Json;
+
+    match command {
+        VaultCommand::List => {
+            let list = vaults.list()?;
+            if json {
+                let list: Vec<_> = list
+                    .iter()
+                    .map(|vault| {
+                        json!({"name": vault.name, "path": vault.path, "own_key": vault.own_key})
+                    })
+                    .collect();
+                return output::print_json(&list);
+            }
+
+            for vault in list {
+                match &vault.name {
+                    None => println!("(default)  {}", vault.path.display()),
+                    Some(name) if vault.own_key => {
+                        println!("{}  {} (own key)", name, vault.path.display())
+                    }
+                    Some(name) => println!("{}  {}", name, vault.path.display()),
+                }
+            }
+            Ok(())
+        }
+        VaultCommand::Create {
+            name,
+            own_key,
+            passphrase,
+            key_file,
+        } => {
+            let vault = vaults.create(&name, own_key)?;
+            if own_key {
+                let mode = key_mode(passphrase, key_file);
+                let crypto = crypto::Crypto::new(vault.key_slot.clone())
+                    .with_passphrase_source(passphrase_source);
+                let initialized = crypto.init(mode).and_then(|key_file| match key_file {
+                    Some(path) => vaults.record_key_file(&vault.key_slot, Some(&path)),
+                    None => Ok(()),
+                });
+                if let Err(e) = initialized {
+                    vaults.delete(&name)?;
+                    return Err(e);
+                }
+            }
+
+            if json {
+                return output::print_json(
+                    &json!({"name": name, "path": vault.path, "own_key": own_key}),
+                );
+            }
+            println!("Vault '{}' created at {}", name, vault.path.display());
+            println!("Use it with: r-auth --profile {} <command>", name);
+            Ok(())
+        }
+        VaultCommand::Delete { name } => {
+            let vault = vaults.get(&name)?;
+            if !confirm(&format!(
+                "WARNING: This will delete vault '{}' with all its accounts{}.\nThis action cannot be undone. Are you sure?",
+                name,
+                if vault.own_key { " and its key" } else { "" }
+            )) {
+                if json {
+                    return output::print_json(&json!({"name": name, "deleted": false}));
+                }
+                println!("Delete cancelled");
+                return Ok(());
+            }
+
+            if vault.own_key {
+                let crypto = crypto::Crypto::new(vault.key_slot.clone());
+                if crypto.key_exists()? {
+                    crypto.reset()?;
+                }
+            }
+            vaults.delete(&name)?;
+
+            if json {
+                return output::print_json(&json!({"name": name, "deleted": true}));
+            }
+            println!("Vault '{}' deleted", name);
+            Ok(())
+        }
+    }
+}
+
+fn run_config(
+    path: &std::path::Path,
+    command: ConfigCommand,
+    overrides: &[String],
+    format: OutputFormat,
+) -> Result<()> {
+    let json = format == OutputFormat::Json;
+    let load = || Config::load(path, |name| std::env::var(name).ok(), overrides);
+    let setting = |config: &Config, key: Key| json!({"key": key.name(), "value": config.settings.get(key), "source": config.source(key)});
+
+    match command {
+        ConfigCommand::Show => {
+            let config = load()?;
+            if json {
+                let settings: Vec<_> = Key::ALL
+                    .into_iter()
+                    .map(|key| setting(&config, key))
+                    .collect();
+                return output::print_json(&json!({"path": path, "settings": settings}));
+            }
+
+            println!("# {}", path.display());
+            for key in Key::ALL {
+                println!(
+                    "{} = {} ({})",
+                    key,
+                    config.settings.get(key),
+                    config.source(key)
+                );
+            }
+            Ok(())
+        }
+        ConfigCommand::Get { key } => {
+            let config = load()?;
+            if json {
+                return output::print_json(&setting(&config, key));
+            }
+            println!(
+                "{} = {} ({})",
+                key,
+                config.settings.get(key),
+                config.source(key)
+            );
+            Ok(())
+        }
+        ConfigCommand::Set { key, value } => {
+            let value = Config::store(path, key, &value)?;
+            if json {
+                return output::print_json(
+                    &json!({"key": key.name(), "value": value, "set": true}),
+                );
+            }
+            println!("Set {} = {} in {}", key, value, path.display());
+            Ok(())
+        }
+        ConfigCommand::Unset { key } => {
+            let removed = Config::remove(path, key)?;
+            if json {
+                return output::print_json(&json!({"key": key.name(), "unset": removed}));
+            }
+            match removed {
+                true => println!("Removed {} from {}", key, path.display()),
+                false => println!("{} is not set in {}", key, path.display()),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn run_recipient(
+    authenticator: &mut authenticator::TOTPAuthenticator,
+    command: RecipientCommand,
+    format: OutputFormat,
+) -> Result<()> {
+    let json = format == OutputFormat::Json;
+    let list = |authenticator: &authenticator::TOTPAuthenticator| -> Vec<String> {
+        authenticator
+            .recipients()
+            .iter()
+            .map(Recipient::to_string)
+            .collect()
+    };
+
+    match command {
+        RecipientCommand::List => {
+            if json {
+                return output::print_json(&json!({"recipients": list(authenticator)}));
+            }
+            if authenticator.recipients().is_empty() {
+                println!("The vault is encrypted to its own key only");
+            } else {
+                println!("The vault is encrypted to:");
+                for recipient in list(authenticator) {
+                    println!("- {}", recipient);
+                }
+            }
+            Ok(())
+        }
+        RecipientCommand::Add { keys, file } => {
+            let mut recipients = Vec::new();
+            for key in &keys {
+                recipients.push(key.parse()?);
+            }
+            for path in &file {
+                let contents = std::fs::read_to_string(path)?;
+                recipients.extend(
+                    recipient::parse_list(&contents)
+                        .map_err(|e| AuthError::Recipient(format!("{}: {}", path.display(), e)))?,
+                );
+            }
+
+            let added = authenticator.add_recipients(&recipients)?;
+            if json {
+                return output::print_json(
+                    &json!({"added": added, "recipients": list(authenticator)}),
+                );
+            }
+            println!(
+                "Added {} recipient(s), the vault is now encrypted to {} key(s)",
+                added,
+                authenticator.recipients().len()
+            );
+            Ok(())
+        }
+        RecipientCommand::Remove { keys } => {
+            let mut recipients = Vec::new();
+            for key in &keys {
+                let recipient = match key.parse::<Recipient>() {
+                    Ok(recipient) => recipient,
+                    Err(e) => authenticator
+                        .recipients()
+                        .iter()
+                        .find(|recipient| recipient.comment() == Some(key.as_str()))
+                        .cloned()
+                        .ok_or(e)?,
+                };
+                recipients.push(recipient);
+            }
+
+            let removed = authenticator.remove_recipients(&recipients)?;
+            if json {
+                return output::print_json(
+                    &json!({"removed": removed, "recipients": list(authenticator)}),
+                );
+            }
+            println!("Removed {} recipient(s)", removed);
+            if removed > 0 {
+                println!("They keep any secret they have seen; rotate the secrets that matter");
+            }
+            Ok(())
+        }
     }
 }
 
 fn run(cli: Cli) -> Result<()> {
-    match cli.command {
-        Commands::Init => {
-            let crypto = crypto::Crypto::new()?;
-            crypto.init()?;
+    let passphrase_source = match cli.passphrase_fd {
+        // Read once up front: several keys may need it and the descriptor can only be
+        // read to the end once
+        Some(fd) => PassphraseSource::Fd(fd).read_once()?,
+        None => PassphraseSource::Prompt,
+    };
+    let output_format = cli.output;
+    let json = output_format == OutputFormat::Json;
+
+    let vaults = Vaults::new()?;
+    let config_path = Config::path()?;
+    let command = match cli.command {
+        Commands::Vault(command) => {
+            return run_vault(&vaults, command, passphrase_source, output_format)
+        }
+        Commands::Config(command) => {
+            return run_config(&config_path, command, &cli.set, output_format)
+        }
+        command => command,
+    };
+    let config = match Config::load(&config_path, |name| std::env::var(name).ok(), &cli.set) {
+        Ok(config) => config,
+        // A broken config must not lock users out of the ways back into their vault
+        Err(e) if command.is_recovery() => {
+            eprintln!(
+                "Warning: using default settings, the config is invalid: {}",
+                e
+            );
+            Config::default()
+        }
+        Err(e) => return Err(e),
+    };
+    let settings = &config.settings;
+    let vault = vaults.select(cli.vault.as_deref(), cli.profile.as_deref())?;
+    let mut crypto =
+        crypto::Crypto::new(vault.key_slot.clone()).with_passphrase_source(passphrase_source);
+    for path in &cli.identity {
+        crypto = crypto.with_identity_file(path)?;
+    }
+
+    match command {
+        Commands::Init {
+            passphrase,
+            key_file,
+        } => {
+            let mode = key_mode(passphrase, key_file.clone());
+            if let Some(path) = crypto.init(mode.clone())? {
+                vaults.record_key_file(&vault.key_slot, Some(&path))?;
+            }
+            if json {
+                return output::print_json(&json!({
+                    "initialized": true,
+                    "passphrase": passphrase,
+                    "key_file": key_file,
+                }));
+            }
+            match mode {
+                KeyMode::Keyring => {
+                    println!("Encryption key generated and stored securely in system keyring")
+                }
+                KeyMode::Passphrase => println!(
+                    "Encryption key generated and protected with your passphrase in {}",
+                    vault.key_slot.identity_file().display()
+                ),
+                KeyMode::KeyFile(path) => {
+                    println!(
+                        "Initialization complete - the vault is encrypted to {}",
+                        path.display()
+                    );
+                    println!("Keep it safe: the accounts cannot be read without it");
+                    return Ok(());
+                }
+            }
             println!("Initialization complete - encryption key generated successfully");
             Ok(())
         }
+        Commands::Reset => {
+            let mut warning = match (&vault.name, vault.own_key) {
+                (Some(name), true) => format!(
+                    "WARNING: This will delete all accounts of vault '{}' and its encryption key.",
+                    name
+                ),
+                (Some(name), false) => {
+                    format!(
+                        "WARNING: This will delete all accounts of vault '{}'.",
+                        name
+                    )
+                }
+                (None, true) => {
+                    "WARNING: This will delete all accounts and the encryption key.".to_string()
+                }
+                (None, false) => format!(
+                    "WARNING: This will delete all accounts in {}.",
+                    vault.path.display()
+                ),
+            };
+            if vault.own_key {
+                let sharing: Vec<_> = vaults
+                    .list()?
+                    .into_iter()
+                    .filter(|other| other.key_slot == vault.key_slot && other.path != vault.path)
+                    .filter_map(|other| other.name)
+                    .collect();
+                if !sharing.is_empty() {
+                    warning.push_str(&format!(
+                        "\nVaults {} share this key and will become unreadable.",
+                        sharing.join(", ")
+                    ));
+                }
+                if let Some(path) = crypto.key_file() {
+                    warning.push_str(&format!(
+                        "\nThe key file {} itself is kept.",
+                        path.display()
+                    ));
+                }
+            }
+            if vault.path.exists() {
+                warning.push_str(
+                    "\nRun 'r-auth backup --to FILE' first to keep a copy of the accounts.",
+                );
+            }
+            if !confirm(&format!(
+                "{}\nThis action cannot be undone. Are you sure?",
+                warning
+            )) {
+                if json {
+                    return output::print_json(&json!({"reset": false}));
+                }
+                println!("Reset cancelled");
+                return Ok(());
+            }
+
+            authenticator::TOTPAuthenticator::reset(&vault.path)?;
+
+            if vault.own_key && crypto.key_exists()? {
+                crypto.reset()?;
+                if crypto.key_file().is_some() {
+                    vaults.record_key_file(&vault.key_slot, None)?;
+                }
+            }
+
+            if json {
+                return output::print_json(&json!({"reset": true}));
+            }
+            println!("Reset complete - all data has been cleared");
+            Ok(())
+        }
+        Commands::ClearClipboard { after, clipboard } => {
+            clipboard::wait_and_clear(clipboard, Duration::from_secs(after))
+        }
+        Commands::Rekey { include } => {
+            if !crypto.key_exists()? {
+                return Err(AuthError::KeyNotFound);
+            }
+            if let Some(missing) = include.iter().find(|path| !path.exists()) {
+                return Err(AuthError::StorageFile(format!(
+                    "{} does not exist",
+                    missing.display()
+                )));
+            }
+
+            let sharing = vaults
+                .list()?
+                .into_iter()
+                .filter(|other| other.key_slot == vault.key_slot && other.path.exists())
+                .map(|other| other.path);
+            let mut paths = vec![vault.path.clone()];
+            for path in sharing.chain(include) {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+            let path_refs: Vec<&std::path::Path> = paths.iter().map(PathBuf::as_path).collect();
+            authenticator::TOTPAuthenticator::rekey(&path_refs, &mut crypto)?;
+
+            if json {
+                return output::print_json(&json!({"rekeyed": paths}));
+            }
+            for path in &paths {
+                println!("Re-encrypted {}", path.display());
+            }
+            println!("Encryption key replaced");
+            Ok(())
+        }
+        Commands::Recover => {
+            let restored = authenticator::TOTPAuthenticator::recover(&vault.path, &crypto)?;
+            if json {
+                return output::print_json(&json!({"restored": restored}));
+            }
+            match restored {
+                Some(count) => println!("Restored {} account(s) from the backup", count),
+                None => println!("The accounts database is readable, nothing to recover"),
+            }
+            Ok(())
+        }
         _ => {
-            let mut authenticator = authenticator::TOTPAuthenticator::new("accounts.json")?;
+            let key_file = crypto.key_file().map(PathBuf::from);
+            let mut authenticator = authenticator::TOTPAuthenticator::new(&vault.path, crypto)?
+                .with_lock_timeout(Duration::from_secs(cli.lock_timeout));
+            if config.source(Key::Issuer) != Source::Default {
+                authenticator = authenticator.with_default_issuer(settings.issuer.clone());
+            }
 
-            match cli.command {
-                Commands::Init => unreachable!(),
+            match command {
+                Commands::Recipient(command) => {
+                    run_recipient(&mut authenticator, command, output_format)
+                }
+                Commands::Init { .. }
+                | Commands::Reset
+                | Commands::Recover
+                | Commands::Rekey { .. }
+                | Commands::ClearClipboard { .. }
+                | Commands::Vault(_)
+                | Commands::Config(_) => unreachable!(),
                 Commands::Add {
                     name,
                     secret_pos,
                     secret,
+                    kind,
+                    algorithm,
+                    digits,
+                    period,
+                    counter,
+                    issuer,
+                    no_qr,
                     force,
                 } => {
                     let secret = secret_pos.or(secret);
+                    if let Some(issuer) = issuer {
+                        authenticator = authenticator.with_default_issuer(issuer);
+                    }
 
-                    if authenticator.account_exists(&name) && !force {
-                        if !confirm(&format!(
-                            "Account '{}' already exists. Do you want to replace it?",
-                            name
-                        )) {
-                            println!("Add cancelled");
-                            return Ok(());
+                    let params = AccountParams {
+                        kind,
+                        algorithm: algorithm.unwrap_or(settings.algorithm),
+                        digits: digits.unwrap_or(settings.digits),
+                        period: period.unwrap_or(settings.period),
+                        counter,
+                    };
+                    // Whether the name is taken is only known under the lock, so ask
+                    // after the first attempt is refused rather than before it
+                    match authenticator.add_account(&name, secret.as_deref(), params, force) {
+                        Ok(_) => {}
+                        Err(AuthError::AccountExists(_)) if settings.confirm => {
+                            if !confirm(&format!(
+                                "Account '{}' already exists. Do you want to replace it?",
+                                name
+                            )) {
+                                if json {
+                                    return output::print_json(
+                                        &json!({"name": name, "added": false}),
+                                    );
+                                }
+                                println!("Add cancelled");
+                                return Ok(());
+                            }
+                            authenticator.add_account(&name, secret.as_deref(), params, true)?;
                         }
+                        Err(e) => return Err(e),
+                    }
+                    let uri = authenticator.accounts()[&name].provisioning_uri(&name)?;
+                    if json {
+                        return output::print_json(
+                            &json!({"name": name, "added": true, "uri": uri}),
+                        );
                     }
 
-                    authenticator.add_account(&name, secret.as_deref(), true)?;
+                    if settings.show_qr && !no_qr {
+                        qr2term::print_qr(uri.as_bytes())
+                            .map_err(|e| AuthError::QrCode(e.to_string()))?;
+                    }
                     println!("Account '{}' added successfully!", name);
                     Ok(())
                 }
@@ -158,22 +1341,45 @@ fn run(cli: Cli) -> Result<()> {
                         AuthError::InvalidSecret("Account name is required".into())
                     })?;
 
-                    if !confirm(&format!(
-                        "Are you sure you want to remove account '{}'?",
-                        name
-                    )) {
+                    // A partial name only ever removes the account the user confirms
+                    let (name, kind) = resolve_account(&authenticator, &name, !json)?;
+                    let prompt = match kind {
+                        MatchKind::Exact => {
+                            format!("Are you sure you want to remove account '{}'?", name)
+                        }
+                        _ => format!(
+                            "Best match is account '{}'. Are you sure you want to remove it?",
+                            name
+                        ),
+                    };
+                    if remove_needs_confirmation(kind, settings.confirm) && !confirm(&prompt) {
+                        if json {
+                            return output::print_json(&json!({"name": name, "removed": false}));
+                        }
                         println!("Remove cancelled");
                         return Ok(());
                     }
 
-                    if authenticator.remove_account(&name) {
-                        println!("Account '{}' removed successfully", name);
-                    } else {
-                        println!("Account '{}' not found", name);
+                    if !authenticator.remove_account(&name)? {
+                        return Err(AuthError::AccountNotFound(name));
+                    }
+                    if json {
+                        return output::print_json(&json!({"name": name, "removed": true}));
                     }
+                    println!("Account '{}' removed successfully", name);
                     Ok(())
                 }
                 Commands::List => {
+                    if json {
+                        let mut accounts: Vec<_> = authenticator
+                            .accounts()
+                            .iter()
+                            .map(|(name, account)| AccountInfo::new(name, account))
+                            .collect();
+                        accounts.sort_by_key(|account| account.name);
+                        return output::print_json(&accounts);
+                    }
+
                     let accounts = authenticator.list_accounts();
                     if accounts.is_empty() {
                         println!("No accounts registered");
@@ -185,43 +1391,420 @@ fn run(cli: Cli) -> Result<()> {
                     }
                     Ok(())
                 }
-                Commands::Show => {
-                    println!("Press Ctrl+C to exit");
-                    authenticator.show_codes()
+                Commands::Show {
+                    clear_after,
+                    clipboard,
+                } => {
+                    if json {
+                        let codes = authenticator.current_codes()?;
+                        let codes: Vec<_> = codes
+                            .iter()
+                            .map(|(name, code)| CodeInfo { name, code })
+                            .collect();
+                        return output::print_json(&codes);
+                    }
+
+                    tui::show(
+                        &mut authenticator,
+                        tui::CopySettings {
+                            backend: clipboard,
+                            clear_after: clear_after.unwrap_or(settings.clear_after),
+                        },
+                    )
                 }
-                Commands::Code { name_pos, name } => {
+                Commands::Code {
+                    name_pos,
+                    name,
+                    copy,
+                    clear_after,
+                    clipboard,
+                    next,
+                    previous,
+                    wait_fresh,
+                } => {
                     let name = name_pos.or(name).ok_or_else(|| {
                         AuthError::InvalidSecret("Account name is required".into())
                     })?;
 
-                    match authenticator.get_code(&name) {
-                        Some(code) => println!("Code for {}: {}", name, code),
-                        None => println!("Account '{}' not found", name),
+                    let clear_after = clear_after.unwrap_or(settings.clear_after);
+                    let (name, _) = resolve_account(&authenticator, &name, !json)?;
+                    if let Some(seconds) = wait_fresh {
+                        authenticator.wait_for_fresh_code(&name, seconds)?;
+                    }
+                    let mut code = authenticator
+                        .get_code(&name)?
+                        .ok_or_else(|| AuthError::AccountNotFound(name.clone()))?;
+                    if !next {
+                        code.next = None;
+                    }
+                    if !previous {
+                        code.previous = None;
+                    }
+                    if copy {
+                        clipboard.open().set(&code.code)?;
+                        if clear_after > 0 {
+                            clipboard::clear_later(
+                                clipboard,
+                                &code.code,
+                                Duration::from_secs(clear_after),
+                            )?;
+                        }
+                    }
+                    if json {
+                        return output::print_json(&CodeInfo {
+                            name: &name,
+                            code: &code,
+                        });
+                    }
+                    match code.remaining_seconds.filter(|_| next) {
+                        Some(remaining) => println!(
+                            "Code for {}: {} (expires in {}s)",
+                            name, code.code, remaining
+                        ),
+                        None => println!("Code for {}: {}", name, code.code),
+                    }
+                    if let Some(next) = &code.next {
+                        println!("Next code: {}", next);
+                    }
+                    if let Some(previous) = &code.previous {
+                        println!("Previous code: {}", previous);
+                    }
+                    if copy {
+                        println!("Copied to clipboard");
                     }
                     Ok(())
                 }
+                Commands::ImportUri {
+                    uris,
+                    file,
+                    on_conflict,
+                } => {
+                    let mut entries = Vec::new();
+                    let mut batches = Vec::new();
+                    let mut invalid = 0;
+                    for (source, uri) in read_uris(uris, file)? {
+                        if migration::is_migration_uri(&uri) {
+                            // A broken export batch aborts the whole import
+                            match migration::decode_uri(&uri) {
+                                Ok(batch) => batches.push(batch),
+                                Err(e) => {
+                                    eprintln!("{}: {}", source, e);
+                                    return Err(e);
+                                }
+                            }
+                            continue;
+                        }
+
+                        match OtpauthUri::parse(&uri) {
+                            Ok(parsed) => entries.push((parsed.name(), Account::from_uri(&parsed))),
+                            Err(e) => {
+                                eprintln!("{}: {}", source, e);
+                                invalid += 1;
+                            }
+                        }
+                    }
+
+                    let export = migration::merge_batches(batches)?;
+                    entries.extend(
+                        export
+                            .accounts
+                            .iter()
+                            .map(|uri| (uri.name(), Account::from_uri(uri))),
+                    );
+
+                    let report = authenticator.import_accounts(entries, on_conflict)?;
+                    print_import_report(&report, &export.unsupported, invalid, output_format)
+                }
+                Commands::Import {
+                    file,
+                    format,
+                    on_conflict,
+                } => {
+                    let data = std::fs::read(&file)?;
+                    let imported = formats::import(format, &data, &|| {
+                        Ok(rpassword::prompt_password("Backup password: ")?)
+                    })?;
 
-                Commands::Reset => {
-                    if !confirm("WARNING: This will delete all accounts and the encryption key.\nThis action cannot be undone. Are you sure?") {
-                        println!("Reset cancelled");
-                        return Ok(());
+                    let report = authenticator.import_accounts(imported.accounts, on_conflict)?;
+                    print_import_report(&report, &imported.unsupported, 0, output_format)
+                }
+                Commands::Export {
+                    format,
+                    file,
+                    encrypt,
+                } => {
+                    let password = if encrypt {
+                        Some(prompt_new_password("Export password")?)
+                    } else {
+                        None
+                    };
+
+                    let exported = match format {
+                        ExportFormat::Aegis => {
+                            formats::aegis::export(authenticator.accounts(), password.as_deref())?
+                        }
+                    };
+
+                    match file {
+                        Some(path) => {
+                            write_private(&path, exported.as_bytes())?;
+                            if json {
+                                output::print_json(&json!({
+                                    "exported": authenticator.accounts().len(),
+                                    "path": path,
+                                    "encrypted": encrypt,
+                                }))?;
+                            } else {
+                                eprintln!(
+                                    "Exported {} account(s) to {}",
+                                    authenticator.accounts().len(),
+                                    path.display()
+                                );
+                            }
+                        }
+                        None => println!("{}", exported),
                     }
+                    if !encrypt {
+                        eprintln!("Warning: the export is not encrypted and contains all secrets");
+                    }
+                    Ok(())
+                }
+                Commands::Backup { to } => {
+                    let passphrase = prompt_new_password("Backup passphrase")?;
+                    let own = authenticator.own_recipient()?;
+                    let info = VaultInfo {
+                        name: vault.name.clone(),
+                        own_key: vault.own_key,
+                        key_file,
+                        recipients: authenticator
+                            .recipients()
+                            .iter()
+                            .filter(|recipient| Some(*recipient) != own.as_ref())
+                            .map(Recipient::to_string)
+                            .collect(),
+                    };
+                    let backup = Backup::new(info, authenticator.accounts().clone());
+                    write_private(&to, &backup.seal(passphrase.into())?)?;
 
-                    if let Ok(authenticator) =
-                        authenticator::TOTPAuthenticator::new("accounts.json")
-                    {
-                        authenticator.reset()?;
+                    if json {
+                        return output::print_json(&json!({
+                            "backed_up": backup.accounts.len(),
+                            "path": to,
+                        }));
                     }
+                    println!(
+                        "Backed up {} account(s) to {}",
+                        backup.accounts.len(),
+                        to.display()
+                    );
+                    println!("Restore them with: r-auth restore --from {}", to.display());
+                    Ok(())
+                }
+                Commands::Restore {
+                    from,
+                    on_conflict,
+                    replace,
+                    dry_run,
+                } => {
+                    let data = std::fs::read(&from)?;
+                    let passphrase = rpassword::prompt_password("Backup passphrase: ")?;
+                    let backup = Backup::open(&data, passphrase.into())?;
+                    let mode = match replace {
+                        true => RestoreMode::Replace,
+                        false => RestoreMode::Merge(on_conflict),
+                    };
+                    let diff = backup.diff(authenticator.accounts());
+                    let key_setup = backup.key_setup_diff(&VaultInfo {
+                        name: vault.name.clone(),
+                        own_key: vault.own_key,
+                        key_file,
+                        recipients: Vec::new(),
+                    });
+                    let recipients: Vec<_> = backup
+                        .recipients()?
+                        .into_iter()
+                        .filter(|recipient| !authenticator.recipients().contains(recipient))
+                        .collect();
 
-                    let crypto = crypto::Crypto::new()?;
-                    if crypto.key_exists()? {
-                        crypto.reset()?;
+                    let removed = match mode {
+                        RestoreMode::Replace => diff.missing.clone(),
+                        RestoreMode::Merge(_) => Vec::new(),
+                    };
+                    let changes = diff.added.len() + diff.changed.len() + removed.len();
+                    if !json {
+                        print_restore_preview(&backup, &diff, &key_setup, mode, recipients.len());
                     }
+                    let proceed = !dry_run
+                        && (changes + recipients.len() == 0
+                            || !settings.confirm
+                            || confirm("Restore the backup?"));
+
+                    let report = match proceed {
+                        true => Some(authenticator.restore(&backup, mode, &recipients)?),
+                        false => None,
+                    };
+                    // The vault's own key, added along with the first recipient, is not counted
+                    let recipients_added = match proceed {
+                        true => recipients.len(),
+                        false => 0,
+                    };
 
-                    println!("Reset complete - all data has been cleared");
+                    if json {
+                        return output::print_json(&json!({
+                            "created_at": backup.created_at,
+                            "vault": backup.vault,
+                            "diff": diff,
+                            "key_setup": key_setup,
+                            "restored": report.as_ref().map(|report| json!({
+                                "added": report.added,
+                                "replaced": report.replaced,
+                                "removed": removed,
+                                "skipped": report.skipped,
+                                "failed": report
+                                    .failed
+                                    .iter()
+                                    .map(|(name, e)| json!({"name": name, "kind": e.kind(), "message": e.to_string()}))
+                                    .collect::<Vec<_>>(),
+                                "recipients_added": recipients_added,
+                            })),
+                        }));
+                    }
+                    let Some(report) = report else {
+                        if !dry_run {
+                            println!("Restore cancelled");
+                        }
+                        return Ok(());
+                    };
+                    for (name, error) in &report.failed {
+                        eprintln!("Failed '{}': {}", name, error);
+                    }
+                    println!(
+                        "Restored {} account(s): {} added, {} replaced, {} removed, {} skipped",
+                        report.added.len() + report.replaced.len(),
+                        report.added.len(),
+                        report.replaced.len(),
+                        removed.len(),
+                        report.skipped.len()
+                    );
+                    if recipients_added > 0 {
+                        println!(
+                            "Shared the vault again with {} recipient(s)",
+                            recipients_added
+                        );
+                    }
+                    Ok(())
+                }
+                Commands::Verify {
+                    name,
+                    code,
+                    skew,
+                    record,
+                } => {
+                    let offset = authenticator.verify_code(&name, &code, skew, record)?;
+                    if json {
+                        return output::print_json(
+                            &json!({"name": name, "valid": true, "offset": offset}),
+                        );
+                    }
+                    match offset {
+                        0 => println!("Code for {} is valid", name),
+                        _ => println!(
+                            "Code for {} is valid ({:+} time step(s) from now)",
+                            name, offset
+                        ),
+                    }
+                    Ok(())
+                }
+                Commands::Resync {
+                    name,
+                    first,
+                    second,
+                    window,
+                } => {
+                    let counter = authenticator.resync_account(&name, &first, &second, window)?;
+                    if json {
+                        return output::print_json(&json!({"name": name, "counter": counter}));
+                    }
+                    match counter {
+                        Some(counter) => {
+                            println!(
+                                "Account '{}' resynchronized, next counter is {}",
+                                name, counter
+                            )
+                        }
+                        None => println!(
+                            "No matching codes found within {} counters, account left unchanged",
+                            window
+                        ),
+                    }
                     Ok(())
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_output_format_does_not_clash_with_export_file() {
+        let cli = Cli::try_parse_from(["r-auth", "--output", "json", "export", "--file", "a.json"])
+            .unwrap();
+        assert_eq!(cli.output, OutputFormat::Json);
+        assert!(matches!(
+            cli.command,
+            Commands::Export { file: Some(_), .. }
+        ));
+    }
+
+    #[test]
+    fn remove_confirms_a_differently_cased_name_with_confirm_off() {
+        let (name, kind) = match matcher::resolve("github", ["GitHub", "GitLab"]) {
+            Resolution::Unique(name, kind) => (name, kind),
+            other => panic!("unexpected resolution {:?}", other),
+        };
+        assert_eq!(name, "GitHub");
+        assert!(remove_needs_confirmation(kind, false));
+        assert!(!remove_needs_confirmation(MatchKind::Exact, false));
+    }
+
+    #[test]
+    fn recovery_commands_run_without_a_valid_config() {
+        let parse = |args: &[&str]| Cli::try_parse_from(args).unwrap().command;
+        assert!(parse(&["r-auth", "recover"]).is_recovery());
+        assert!(parse(&["r-auth", "restore", "--from", "b.age"]).is_recovery());
+        assert!(!parse(&["r-auth", "list"]).is_recovery());
+    }
+
+    #[test]
+    fn restore_either_merges_or_replaces() {
+        let cli =
+            Cli::try_parse_from(["r-auth", "restore", "--from", "b.age", "--replace"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Restore {
+                replace: true,
+                on_conflict: OnConflict::Skip,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from([
+            "r-auth",
+            "restore",
+            "--from",
+            "b.age",
+            "--replace",
+            "--on-conflict",
+            "rename",
+        ])
+        .is_err());
+    }
+}
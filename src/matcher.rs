@@ -0,0 +1,176 @@
+//! Resolves a loosely typed query, such as `github`, to an account name such as
+//! `GitHub (work)`
+
+/// How a query matched an account name, from strongest to weakest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    /// Byte-for-byte identical
+    Exact,
+    /// Identical ignoring case
+    CaseInsensitive,
+    Prefix,
+    Substring,
+    /// The query's characters appear in order
+    Fuzzy,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Unique(String, MatchKind),
+    /// Several names matched equally well, best candidates first
+    Ambiguous(Vec<String>, MatchKind),
+    NotFound,
+}
+
+/// Resolves `query` against `names` trying, in order, an exact match, then a
+/// case-insensitive exact, prefix, substring and fuzzy match. The first kind with
+/// any match decides the result. A blank query matches nothing, rather than every name
+/// as a prefix
+pub fn resolve<'a>(query: &str, names: impl IntoIterator<Item = &'a str>) -> Resolution {
+    if query.trim().is_empty() {
+        return Resolution::NotFound;
+    }
+    let names: Vec<&str> = names.into_iter().collect();
+    if names.contains(&query) {
+        return Resolution::Unique(query.to_string(), MatchKind::Exact);
+    }
+
+    let query = query.trim().to_lowercase();
+    let lowered: Vec<(String, &str)> = names
+        .iter()
+        .map(|&name| (name.to_lowercase(), name))
+        .collect();
+
+    for kind in [
+        MatchKind::CaseInsensitive,
+        MatchKind::Prefix,
+        MatchKind::Substring,
+        MatchKind::Fuzzy,
+    ] {
+        let mut hits: Vec<(usize, &str)> = lowered
+            .iter()
+            .filter_map(|(lower, name)| score(kind, &query, lower).map(|s| (s, *name)))
+            .collect();
+        hits.sort();
+
+        match hits.as_slice() {
+            [] => continue,
+            [(_, name)] => return Resolution::Unique(name.to_string(), kind),
+            _ => {
+                let names = hits.into_iter().map(|(_, name)| name.to_string()).collect();
+                return Resolution::Ambiguous(names, kind);
+            }
+        }
+    }
+    Resolution::NotFound
+}
+
+/// Ranks `name` against `query` for one kind of match, lower is better
+fn score(kind: MatchKind, query: &str, name: &str) -> Option<usize> {
+    match kind {
+        MatchKind::Exact | MatchKind::CaseInsensitive => (name == query).then_some(0),
+        MatchKind::Prefix => name.starts_with(query).then_some(0),
+        MatchKind::Substring => name.find(query),
+        MatchKind::Fuzzy => fuzzy_span(query, name),
+    }
+}
+
+/// Length of the shortest stretch of `name` containing the characters of `query` in
+/// order, or `None` if they do not all appear. Tighter matches score lower
+fn fuzzy_span(query: &str, name: &str) -> Option<usize> {
+    let query: Vec<char> = query.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let first = *query.first()?;
+
+    (0..name.len())
+        .filter(|&start| name[start] == first)
+        .filter_map(|start| {
+            let mut pending = query.iter().peekable();
+            for (i, c) in name.iter().enumerate().skip(start) {
+                if pending.next_if(|&&q| q == *c).is_some() && pending.peek().is_none() {
+                    return Some(i + 1 - start);
+                }
+            }
+            None
+        })
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 5] = [
+        "GitHub (work)",
+        "GitHub (personal)",
+        "GitLab",
+        "Amazon Web Services",
+        "aws",
+    ];
+
+    #[test]
+    fn exact_match_wins_even_when_others_contain_it() {
+        assert_eq!(
+            resolve("aws", NAMES),
+            Resolution::Unique("aws".into(), MatchKind::Exact)
+        );
+        assert_eq!(
+            resolve("GITLAB", NAMES),
+            Resolution::Unique("GitLab".into(), MatchKind::CaseInsensitive)
+        );
+    }
+
+    #[test]
+    fn falls_through_prefix_substring_and_fuzzy() {
+        assert_eq!(
+            resolve("gitl", NAMES),
+            Resolution::Unique("GitLab".into(), MatchKind::Prefix)
+        );
+        assert_eq!(
+            resolve("work", NAMES),
+            Resolution::Unique("GitHub (work)".into(), MatchKind::Substring)
+        );
+        assert_eq!(
+            resolve("amzws", NAMES),
+            Resolution::Unique("Amazon Web Services".into(), MatchKind::Fuzzy)
+        );
+        assert_eq!(resolve("dropbox", NAMES), Resolution::NotFound);
+    }
+
+    #[test]
+    fn blank_query_matches_nothing() {
+        assert_eq!(resolve("", NAMES), Resolution::NotFound);
+        assert_eq!(resolve("  \t", NAMES), Resolution::NotFound);
+        assert_eq!(resolve("", ["GitLab"]), Resolution::NotFound);
+        assert_eq!(
+            resolve(" gitlab ", NAMES),
+            Resolution::Unique("GitLab".into(), MatchKind::CaseInsensitive)
+        );
+    }
+
+    #[test]
+    fn reports_ambiguity_at_the_strongest_kind() {
+        assert_eq!(
+            resolve("github", NAMES),
+            Resolution::Ambiguous(
+                vec!["GitHub (personal)".into(), "GitHub (work)".into()],
+                MatchKind::Prefix
+            )
+        );
+    }
+
+    #[test]
+    fn fuzzy_candidates_are_ordered_by_tightness() {
+        assert_eq!(fuzzy_span("gh", "github"), Some(4));
+        assert_eq!(fuzzy_span("gh", "ghost"), Some(2));
+        assert_eq!(fuzzy_span("hg", "github"), None);
+        assert_eq!(
+            resolve("gb", ["gitlab", "gb-lite", "github"]),
+            Resolution::Unique("gb-lite".into(), MatchKind::Prefix)
+        );
+        assert_eq!(
+            resolve("ghb", ["github", "ghub"]),
+            Resolution::Ambiguous(vec!["ghub".into(), "github".into()], MatchKind::Fuzzy)
+        );
+    }
+}
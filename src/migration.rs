@@ -0,0 +1,500 @@
+//! Decoder for Google Authenticator "Transfer accounts" exports
+//!
+//! Exports are `otpauth-migration://offline?data=...` URIs whose `data` parameter is a
+//! base64 encoded `MigrationPayload` protobuf message. Large exports are split into
+//! several URIs (batches) that share a batch id.
+
+use base64::Engine;
+use url::Url;
+
+use crate::account::OtpKind;
+use crate::error::{AuthError, Result};
+use crate::totp::{split_label, Algorithm, OtpauthUri};
+
+/// One decoded `otpauth-migration` URI
+#[derive(Debug, Default)]
+pub struct MigrationBatch {
+    pub accounts: Vec<OtpauthUri>,
+    /// Entries that cannot be represented, with the reason
+    pub unsupported: Vec<(String, String)>,
+    pub batch_size: u64,
+    pub batch_index: u64,
+    pub batch_id: i64,
+}
+
+/// Whether `uri` is a Google Authenticator export rather than an otpauth URI
+pub fn is_migration_uri(uri: &str) -> bool {
+    uri.trim_start()
+        .to_ascii_lowercase()
+        .starts_with("otpauth-migration:")
+}
+
+/// Decodes a single `otpauth-migration://offline?data=...` URI
+pub fn decode_uri(uri: &str) -> Result<MigrationBatch> {
+    let invalid = |msg: String| AuthError::InvalidMigration(msg);
+
+    let url = Url::parse(uri.trim()).map_err(|e| invalid(e.to_string()))?;
+    if url.scheme() != "otpauth-migration" {
+        return Err(invalid(format!(
+            "expected otpauth-migration:// scheme, got {}://",
+            url.scheme()
+        )));
+    }
+
+    let data = url
+        .query_pairs()
+        .find(|(key, _)| key == "data")
+        .map(|(_, value)| value.into_owned())
+        .ok_or_else(|| invalid("missing data parameter".into()))?;
+
+    // Unescaped '+' characters in the payload are decoded as spaces by the query parser
+    let data = data.replace(' ', "+");
+    let payload = base64::engine::general_purpose::STANDARD_NO_PAD
+        .decode(data.trim_end_matches('='))
+        .map_err(|e| invalid(format!("invalid base64 payload: {}", e)))?;
+
+    decode_payload(&payload)
+}
+
+/// Combines the batches of one export, checking that none are missing or duplicated
+pub fn merge_batches(mut batches: Vec<MigrationBatch>) -> Result<MigrationBatch> {
+    let invalid = |msg: String| AuthError::InvalidMigration(msg);
+
+    let Some(first) = batches.first() else {
+        return Ok(MigrationBatch::default());
+    };
+    let (batch_id, batch_size) = (first.batch_id, first.batch_size.max(1));
+
+    if batches.iter().any(|b| b.batch_id != batch_id) {
+        return Err(invalid("URIs belong to different exports".into()));
+    }
+
+    // The size comes from the payload, so it is checked against the URIs given rather
+    // than used to build the expected list of indices
+    if batch_size > batches.len() as u64 {
+        return Err(invalid(format!(
+            "incomplete export: got {} of {} batches",
+            batches.len(),
+            batch_size
+        )));
+    }
+
+    batches.sort_by_key(|b| b.batch_index);
+    let indices: Vec<u64> = batches.iter().map(|b| b.batch_index).collect();
+    let complete = indices.len() as u64 == batch_size
+        && indices.iter().enumerate().all(|(i, &b)| b == i as u64);
+    if !complete {
+        return Err(invalid(format!(
+            "incomplete export: got batches {:?} of {}",
+            indices.iter().map(|i| i + 1).collect::<Vec<_>>(),
+            batch_size
+        )));
+    }
+
+    let mut merged = MigrationBatch {
+        batch_size,
+        batch_id,
+        ..Default::default()
+    };
+    for batch in batches {
+        merged.accounts.extend(batch.accounts);
+        merged.unsupported.extend(batch.unsupported);
+    }
+    Ok(merged)
+}
+
+/// Decodes a `MigrationPayload` message:
+///
+/// ```text
+/// message MigrationPayload {
+///   repeated OtpParameters otp_parameters = 1;
+///   int32 version = 2;
+///   int32 batch_size = 3;
+///   int32 batch_index = 4;
+///   int32 batch_id = 5;
+/// }
+/// ```
+fn decode_payload(payload: &[u8]) -> Result<MigrationBatch> {
+    let mut batch = MigrationBatch {
+        batch_size: 1,
+        ..Default::default()
+    };
+
+    let mut reader = ProtoReader::new(payload);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, Value::Bytes(bytes)) => match decode_otp_parameters(bytes)? {
+                Ok(uri) => batch.accounts.push(uri),
+                Err(entry) => batch.unsupported.push(entry),
+            },
+            (3, Value::Varint(size)) => batch.batch_size = size,
+            (4, Value::Varint(index)) => batch.batch_index = index,
+            (5, Value::Varint(id)) => batch.batch_id = id as i64,
+            _ => {}
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Decodes an `OtpParameters` message into account parameters:
+///
+/// ```text
+/// message OtpParameters {
+///   bytes secret = 1;
+///   string name = 2;
+///   string issuer = 3;
+///   Algorithm algorithm = 4;   // 0 unspecified, 1 SHA1, 2 SHA256, 3 SHA512, 4 MD5
+///   DigitCount digits = 5;     // 0 unspecified, 1 six, 2 eight
+///   OtpType type = 6;          // 0 unspecified, 1 HOTP, 2 TOTP
+///   int64 counter = 7;
+/// }
+/// ```
+///
+/// Entries using values r-auth cannot represent are returned as `Err((name, reason))`
+fn decode_otp_parameters(
+    message: &[u8],
+) -> Result<std::result::Result<OtpauthUri, (String, String)>> {
+    let invalid = |msg: String| AuthError::InvalidMigration(msg);
+
+    let mut secret = Vec::new();
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let (mut algorithm, mut digits, mut kind, mut counter) = (0, 0, 0, 0);
+
+    let mut reader = ProtoReader::new(message);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, Value::Bytes(bytes)) => secret = bytes.to_vec(),
+            (2, Value::Bytes(bytes)) => {
+                name = String::from_utf8(bytes.to_vec())
+                    .map_err(|_| invalid("account name is not valid UTF-8".into()))?
+            }
+            (3, Value::Bytes(bytes)) => {
+                issuer = String::from_utf8(bytes.to_vec())
+                    .map_err(|_| invalid("issuer is not valid UTF-8".into()))?
+            }
+            (4, Value::Varint(v)) => algorithm = v,
+            (5, Value::Varint(v)) => digits = v,
+            (6, Value::Varint(v)) => kind = v,
+            (7, Value::Varint(v)) => counter = v,
+            _ => {}
+        }
+    }
+
+    let (label_issuer, account) = split_label(&name);
+    let issuer = Some(issuer.trim().to_string())
+        .filter(|i| !i.is_empty())
+        .or(label_issuer);
+    let display = match &issuer {
+        Some(issuer) => format!("{}:{}", issuer, account),
+        None => account.clone(),
+    };
+    let unsupported = |reason: &str| Ok(Err((display.clone(), reason.to_string())));
+
+    let algorithm = match algorithm {
+        0 | 1 => Algorithm::Sha1,
+        2 => Algorithm::Sha256,
+        3 => Algorithm::Sha512,
+        4 => return unsupported("MD5 is not supported"),
+        other => return unsupported(&format!("unknown algorithm {}", other)),
+    };
+    let digits = match digits {
+        0 | 1 => 6,
+        2 => 8,
+        other => return unsupported(&format!("unknown digit count {}", other)),
+    };
+    let kind = match kind {
+        1 => OtpKind::Hotp,
+        0 | 2 => OtpKind::Totp,
+        other => return unsupported(&format!("unknown OTP type {}", other)),
+    };
+    if secret.is_empty() {
+        return unsupported("missing secret");
+    }
+    if account.is_empty() {
+        return unsupported("missing account name");
+    }
+
+    Ok(Ok(OtpauthUri {
+        kind,
+        account,
+        issuer,
+        secret: base32::encode(base32::Alphabet::RFC4648 { padding: true }, &secret),
+        algorithm,
+        digits,
+        // Google Authenticator only exports 30 second tokens
+        period: 30,
+        counter,
+    }))
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Minimal protobuf wire format reader, sufficient for the migration payload
+struct ProtoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn truncated() -> AuthError {
+        AuthError::InvalidMigration("truncated protobuf message".into())
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos).ok_or_else(Self::truncated)?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(AuthError::InvalidMigration("varint is too long".into()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(Self::truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(Self::truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire_type => {
+                return Err(AuthError::InvalidMigration(format!(
+                    "unsupported wire type {}",
+                    wire_type
+                )))
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(field << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn varint_field(field: u64, value: u64, out: &mut Vec<u8>) {
+        varint(field << 3, out);
+        varint(value, out);
+    }
+
+    #[derive(Clone, Copy)]
+    struct Entry<'a> {
+        secret: &'a [u8],
+        name: &'a str,
+        issuer: &'a str,
+        algorithm: u64,
+        digits: u64,
+        kind: u64,
+        counter: u64,
+    }
+
+    fn payload_uri(entries: &[Entry], size: u64, index: u64, id: u64) -> String {
+        let mut payload = Vec::new();
+        for entry in entries {
+            let mut message = Vec::new();
+            bytes_field(1, entry.secret, &mut message);
+            bytes_field(2, entry.name.as_bytes(), &mut message);
+            bytes_field(3, entry.issuer.as_bytes(), &mut message);
+            varint_field(4, entry.algorithm, &mut message);
+            varint_field(5, entry.digits, &mut message);
+            varint_field(6, entry.kind, &mut message);
+            varint_field(7, entry.counter, &mut message);
+            bytes_field(1, &message, &mut payload);
+        }
+        varint_field(2, 1, &mut payload);
+        varint_field(3, size, &mut payload);
+        varint_field(4, index, &mut payload);
+        varint_field(5, id, &mut payload);
+
+        let data = base64::engine::general_purpose::STANDARD.encode(payload);
+        let mut url = Url::parse("otpauth-migration://offline").unwrap();
+        url.query_pairs_mut().append_pair("data", &data);
+        url.to_string()
+    }
+
+    const SECRET: &[u8] = b"Hello!\xde\xad\xbe\xef";
+
+    #[test]
+    fn decodes_single_batch() {
+        let uri = payload_uri(
+            &[
+                Entry {
+                    secret: SECRET,
+                    name: "GitHub:alice",
+                    issuer: "",
+                    algorithm: 1,
+                    digits: 1,
+                    kind: 2,
+                    counter: 0,
+                },
+                Entry {
+                    secret: SECRET,
+                    name: "bob",
+                    issuer: "VPN",
+                    algorithm: 3,
+                    digits: 2,
+                    kind: 1,
+                    counter: 9,
+                },
+            ],
+            1,
+            0,
+            42,
+        );
+
+        let batch = merge_batches(vec![decode_uri(&uri).unwrap()]).unwrap();
+        assert!(batch.unsupported.is_empty());
+        assert_eq!(batch.accounts.len(), 2);
+
+        let github = &batch.accounts[0];
+        assert_eq!(github.name(), "GitHub:alice");
+        assert_eq!(github.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(github.kind, OtpKind::Totp);
+        assert_eq!(github.algorithm, Algorithm::Sha1);
+        assert_eq!(github.digits, 6);
+
+        let vpn = &batch.accounts[1];
+        assert_eq!(vpn.name(), "VPN:bob");
+        assert_eq!(vpn.kind, OtpKind::Hotp);
+        assert_eq!(vpn.algorithm, Algorithm::Sha512);
+        assert_eq!(vpn.digits, 8);
+        assert_eq!(vpn.counter, 9);
+    }
+
+    #[test]
+    fn reports_unsupported_entries() {
+        let uri = payload_uri(
+            &[Entry {
+                secret: SECRET,
+                name: "legacy",
+                issuer: "",
+                algorithm: 4,
+                digits: 1,
+                kind: 2,
+                counter: 0,
+            }],
+            1,
+            0,
+            1,
+        );
+
+        let batch = decode_uri(&uri).unwrap();
+        assert!(batch.accounts.is_empty());
+        assert_eq!(batch.unsupported[0].0, "legacy");
+    }
+
+    #[test]
+    fn merges_multi_batch_exports() {
+        let entry = |name| Entry {
+            secret: SECRET,
+            name,
+            issuer: "",
+            algorithm: 1,
+            digits: 1,
+            kind: 2,
+            counter: 0,
+        };
+        let second = decode_uri(&payload_uri(&[entry("b")], 2, 1, 7)).unwrap();
+        let first = decode_uri(&payload_uri(&[entry("a")], 2, 0, 7)).unwrap();
+
+        let merged = merge_batches(vec![second, first]).unwrap();
+        let names: Vec<String> = merged.accounts.iter().map(|a| a.name()).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn rejects_incomplete_or_mixed_exports() {
+        let entry = Entry {
+            secret: SECRET,
+            name: "a",
+            issuer: "",
+            algorithm: 1,
+            digits: 1,
+            kind: 2,
+            counter: 0,
+        };
+        let batch = |index, id| decode_uri(&payload_uri(&[entry], 2, index, id));
+
+        assert!(merge_batches(vec![batch(0, 7).unwrap()]).is_err());
+        assert!(merge_batches(vec![batch(0, 7).unwrap(), batch(1, 8).unwrap()]).is_err());
+        assert!(merge_batches(vec![batch(0, 7).unwrap(), batch(0, 7).unwrap()]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_batch_size() {
+        let entry = Entry {
+            secret: SECRET,
+            name: "a",
+            issuer: "",
+            algorithm: 1,
+            digits: 1,
+            kind: 2,
+            counter: 0,
+        };
+        let batch = decode_uri(&payload_uri(&[entry], 1 << 62, 0, 7)).unwrap();
+        assert!(matches!(
+            merge_batches(vec![batch]),
+            Err(AuthError::InvalidMigration(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        assert!(decode_uri("otpauth-migration://offline").is_err());
+        assert!(decode_uri("otpauth-migration://offline?data=%%%").is_err());
+        // Length-delimited field claiming more bytes than are present
+        let data = base64::engine::general_purpose::STANDARD.encode([0x0a, 0x10, 0x01]);
+        assert!(decode_uri(&format!("otpauth-migration://offline?data={}", data)).is_err());
+    }
+}
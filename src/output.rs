@@ -0,0 +1,160 @@
+//! Machine-readable `--output json` rendering of accounts, codes and errors
+
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use std::io::{stdout, Write};
+use std::str::FromStr;
+
+use r_auth::account::{Account, OtpKind};
+use r_auth::authenticator::Code;
+use r_auth::error::{AuthError, Result};
+use r_auth::totp::Algorithm;
+
+/// How command results and errors are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// A single JSON document on stdout
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(AuthError::InvalidParameter(format!(
+                "unknown output format '{}', expected text or json",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Text => f.write_str("text"),
+            OutputFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+/// Account metadata as listed by `list`; the secret is never included
+#[derive(Debug, Serialize)]
+pub struct AccountInfo<'a> {
+    pub name: &'a str,
+    #[serde(rename = "type")]
+    pub kind: OtpKind,
+    pub issuer: Option<&'a str>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    /// Time step in seconds, TOTP only
+    pub period: Option<u64>,
+    /// Next counter value, HOTP only
+    pub counter: Option<u64>,
+    pub tags: &'a [String],
+    pub notes: Option<&'a str>,
+    pub created_at: Option<u64>,
+}
+
+impl<'a> AccountInfo<'a> {
+    pub fn new(name: &'a str, account: &'a Account) -> Self {
+        let totp = account.kind == OtpKind::Totp;
+        Self {
+            name,
+            kind: account.kind,
+            issuer: account.issuer.as_deref(),
+            algorithm: account.algorithm,
+            digits: account.digits,
+            period: totp.then_some(account.period),
+            counter: (!totp).then_some(account.counter),
+            tags: &account.tags,
+            notes: account.notes.as_deref(),
+            created_at: account.created_at,
+        }
+    }
+}
+
+/// A code as printed by `code` and `show`
+#[derive(Debug, Serialize)]
+pub struct CodeInfo<'a> {
+    pub name: &'a str,
+    #[serde(flatten)]
+    pub code: &'a Code,
+}
+
+/// Prints `value` as pretty JSON on stdout
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    // Write rather than println! so a closed pipe is an error instead of a panic
+    let mut stdout = stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// The JSON document printed in place of a result when a command fails
+pub fn error_json(error: &AuthError) -> serde_json::Value {
+    json!({
+        "error": {
+            "kind": error.kind(),
+            "message": error.to_string(),
+            "exit_code": error.exit_code(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r_auth::account::AccountParams;
+
+    #[test]
+    fn account_info_omits_secret() {
+        let account = Account::new("JBSWY3DPEHPK3PXP".into(), AccountParams::default());
+        let value = serde_json::to_value(AccountInfo::new("GitHub", &account)).unwrap();
+
+        assert_eq!(value["name"], "GitHub");
+        assert_eq!(value["type"], "totp");
+        assert_eq!(value["algorithm"], "SHA1");
+        assert_eq!(value["period"], 30);
+        assert!(value["counter"].is_null());
+        assert!(!value.to_string().contains("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn errors_serialize_kind_and_exit_code() {
+        let value = error_json(&AuthError::AccountNotFound("GitHub".into()));
+
+        assert_eq!(value["error"]["kind"], "account_not_found");
+        assert_eq!(value["error"]["exit_code"], 34);
+        assert_eq!(value["error"]["message"], "Account 'GitHub' not found");
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            AuthError::Io(std::io::Error::other("io")),
+            AuthError::Base32DecodeError,
+            AuthError::InvalidSecret(String::new()),
+            AuthError::InvalidParameter(String::new()),
+            AuthError::KeyExists,
+            AuthError::KeyNotFound,
+            AuthError::CorruptStorage(String::new()),
+            AuthError::StorageLocked(String::new()),
+            AuthError::AccountExists(String::new()),
+            AuthError::AccountNotFound(String::new()),
+        ];
+        let mut codes: Vec<_> = errors.iter().map(AuthError::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|&code| code > 2));
+    }
+}
@@ -0,0 +1,174 @@
+//! Public keys a shared vault is encrypted to: age X25519 keys and SSH ed25519 or RSA keys
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{AuthError, Result};
+
+/// A public key that can read a vault
+#[derive(Clone)]
+pub enum Recipient {
+    /// An age public key, `age1...`
+    X25519(age::x25519::Recipient),
+    /// An `ssh-ed25519` or `ssh-rsa` public key, with the comment that followed it
+    Ssh(age::ssh::Recipient, Option<String>),
+}
+
+impl Recipient {
+    /// The public key alone, without the comment of an SSH key
+    pub fn key(&self) -> String {
+        match self {
+            Recipient::X25519(recipient) => recipient.to_string(),
+            Recipient::Ssh(recipient, _) => recipient.to_string(),
+        }
+    }
+
+    /// The comment of an SSH key, usually naming its owner such as `alice@laptop`
+    pub fn comment(&self) -> Option<&str> {
+        match self {
+            Recipient::X25519(_) => None,
+            Recipient::Ssh(_, comment) => comment.as_deref(),
+        }
+    }
+
+    pub(crate) fn as_age(&self) -> &dyn age::Recipient {
+        match self {
+            Recipient::X25519(recipient) => recipient,
+            Recipient::Ssh(recipient, _) => recipient,
+        }
+    }
+}
+
+/// Recipients are the same when their keys are, whatever their comments
+impl PartialEq for Recipient {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Recipient {}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient({})", self)
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.starts_with("age1") {
+            let recipient = s
+                .parse()
+                .map_err(|e| AuthError::Recipient(format!("invalid age key '{}': {}", s, e)))?;
+            return Ok(Recipient::X25519(recipient));
+        }
+
+        if s.starts_with("ssh-") {
+            use age::ssh::ParseRecipientKeyError;
+
+            let recipient = s.parse().map_err(|e| {
+                let reason = match e {
+                    ParseRecipientKeyError::Unsupported(kind) => {
+                        format!("{} keys are not supported", kind)
+                    }
+                    ParseRecipientKeyError::RsaModulusTooSmall => {
+                        "RSA keys must be at least 2048 bits".to_string()
+                    }
+                    ParseRecipientKeyError::RsaModulusTooLarge => {
+                        "RSA keys must be at most 4096 bits".to_string()
+                    }
+                    ParseRecipientKeyError::Invalid(reason) => reason.to_string(),
+                    ParseRecipientKeyError::Ignore => "unsupported SSH key".to_string(),
+                };
+                AuthError::Recipient(format!("invalid SSH key: {}", reason))
+            })?;
+            let comment = s.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+            return Ok(Recipient::Ssh(
+                recipient,
+                Some(comment).filter(|comment| !comment.is_empty()),
+            ));
+        }
+
+        Err(AuthError::Recipient(format!(
+            "'{}' is neither an age public key (age1...) nor an SSH public key (ssh-ed25519 or ssh-rsa)",
+            s
+        )))
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.key())?;
+        match self.comment() {
+            Some(comment) => write!(f, " {}", comment),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses a list of recipients, one per line; blank lines and lines starting with '#'
+/// are ignored, as in age recipients files and `authorized_keys`
+pub fn parse_list(contents: &str) -> Result<Vec<Recipient>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            line.parse().map_err(|e| match e {
+                AuthError::Recipient(message) => {
+                    AuthError::Recipient(format!("line {}: {}", number, message))
+                }
+                e => e,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSH_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPU/+dwHibUuLd2D2xrUtbnS2n7iC00ituAMjnr2z14x";
+
+    #[test]
+    fn parses_age_and_ssh_keys() {
+        let age_key = age::x25519::Identity::generate().to_public().to_string();
+        let recipient: Recipient = age_key.parse().unwrap();
+        assert!(matches!(recipient, Recipient::X25519(_)));
+        assert_eq!(recipient.to_string(), age_key);
+
+        let recipient: Recipient = format!("{} alice@laptop", SSH_KEY).parse().unwrap();
+        assert_eq!(recipient.key(), SSH_KEY);
+        assert_eq!(recipient.comment(), Some("alice@laptop"));
+        assert_eq!(recipient, SSH_KEY.parse().unwrap());
+
+        for invalid in [
+            "age1invalid",
+            "ssh-ed25519 AAAA",
+            "ssh-dss AAAAB3NzaC1kc3M",
+            "alice",
+        ] {
+            assert!(
+                matches!(invalid.parse::<Recipient>(), Err(AuthError::Recipient(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn list_skips_comments_and_reports_bad_lines() {
+        let age_key = age::x25519::Identity::generate().to_public().to_string();
+        let list = parse_list(&format!("# team\n\n{}\n  {} bob\n", age_key, SSH_KEY)).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].comment(), Some("bob"));
+
+        let error = parse_list(&format!("{}\nnot a key\n", age_key)).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+    }
+}
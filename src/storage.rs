@@ -0,0 +1,417 @@
+use fs4::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::account::{Account, AccountParams};
+use crate::error::{AuthError, Result};
+use crate::recipient::{self, Recipient};
+
+/// Decrypted contents of the accounts database, tagged with its schema version
+#[derive(Deserialize)]
+#[serde(tag = "version")]
+enum Store {
+    #[serde(rename = "1")]
+    V1 { accounts: HashMap<String, Account> },
+}
+
+/// Borrowing counterpart of [`Store`] used when saving
+#[derive(Serialize)]
+#[serde(tag = "version")]
+enum StoreRef<'a> {
+    #[serde(rename = "1")]
+    V1 {
+        accounts: &'a HashMap<String, Account>,
+    },
+}
+
+/// Only checks whether the decrypted contents carry a schema version, ignoring the rest
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: Option<serde::de::IgnoredAny>,
+}
+
+/// Account created from a legacy `name -> secret` entry
+fn legacy_account(secret: String) -> Account {
+    Account {
+        created_at: None,
+        last_verified_step: None,
+        ..Account::new(secret, AccountParams::default())
+    }
+}
+
+/// Accounts read from storage, and whether they were migrated from an older schema
+pub struct Decoded {
+    pub accounts: HashMap<String, Account>,
+    pub migrated: bool,
+}
+
+/// Parses decrypted storage contents, migrating unversioned `name -> secret` maps
+/// Contents with a `version` key are never taken for a legacy map, so an unknown
+/// version or a damaged versioned store is reported rather than migrated
+pub fn decode(contents: &str) -> Result<Decoded> {
+    let probe: VersionProbe = serde_json::from_str(contents)
+        .map_err(|e| AuthError::InvalidStorage(format!("Invalid JSON: {}", e)))?;
+
+    if probe.version.is_some() {
+        let Store::V1 { accounts } = serde_json::from_str::<Store>(contents)
+            .map_err(|e| AuthError::InvalidStorage(format!("Invalid store: {}", e)))?;
+        return Ok(Decoded {
+            accounts,
+            migrated: false,
+        });
+    }
+
+    let legacy: HashMap<String, String> = serde_json::from_str(contents)
+        .map_err(|e| AuthError::InvalidStorage(format!("Invalid JSON: {}", e)))?;
+
+    Ok(Decoded {
+        accounts: legacy
+            .into_iter()
+            .map(|(name, secret)| (name, legacy_account(secret)))
+            .collect(),
+        migrated: true,
+    })
+}
+
+/// Serializes accounts using the current schema version
+pub fn encode(accounts: &HashMap<String, Account>) -> Result<String> {
+    serde_json::to_string_pretty(&StoreRef::V1 { accounts })
+        .map_err(|e| AuthError::InvalidStorage(format!("Failed to serialize: {}", e)))
+}
+
+/// Location of the previous generation of the accounts database
+pub fn backup_path(storage_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.bak", storage_file))
+}
+
+/// Replaces `storage_file` with `data` so that a crash at any point leaves a readable
+/// database: the new contents are written and synced to a temporary file in the same
+/// directory, the current file is preserved as [`backup_path`], and the temporary file
+/// is renamed over the original
+pub fn write_atomic(storage_file: &str, data: &[u8]) -> Result<()> {
+    let path = Path::new(storage_file);
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let tmp = PathBuf::from(format!("{}.tmp", storage_file));
+    write_synced(&tmp, data)?;
+
+    if path.exists() {
+        let backup = backup_path(storage_file);
+        let backup_tmp = PathBuf::from(format!("{}.tmp", backup.display()));
+        let previous = fs::read(path)
+            .map_err(|e| AuthError::StorageFile(format!("Failed to read storage: {}", e)))?;
+        write_synced(&backup_tmp, &previous)?;
+        rename(&backup_tmp, &backup)?;
+    }
+
+    rename(&tmp, path)?;
+    sync_dir(dir)
+}
+
+/// Replaces `path` with `data` like [`write_atomic`], without keeping the previous
+/// contents
+pub fn replace_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    write_synced(&tmp, data)?;
+    rename(&tmp, path)?;
+    sync_dir(dir)
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| AuthError::StorageFile(format!("Failed to open for writing: {}", e)))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| AuthError::StorageFile(format!("Failed to write: {}", e)))
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to)
+        .map_err(|e| AuthError::StorageFile(format!("Failed to replace {}: {}", to.display(), e)))
+}
+
+/// Persists the renames in `dir`; directories cannot be opened for syncing on Windows
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| AuthError::StorageFile(format!("Failed to sync directory: {}", e)))?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Location of the list of recipients `storage_file` is encrypted to, besides the key
+/// of its owner
+pub fn recipients_path(storage_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.recipients", storage_file))
+}
+
+/// Reads the recipients of `storage_file`; none when it has no list
+pub fn read_recipients(storage_file: &str) -> Result<Vec<Recipient>> {
+    let path = recipients_path(storage_file);
+    match read_if_exists(&path)? {
+        Some(contents) => recipient::parse_list(&String::from_utf8_lossy(&contents))
+            .map_err(|e| AuthError::Recipient(format!("{}: {}", path.display(), e))),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Replaces the recipients list of `storage_file`, deleting it when `recipients` is empty
+pub fn write_recipients(storage_file: &str, recipients: &[Recipient]) -> Result<()> {
+    let path = recipients_path(storage_file);
+    if recipients.is_empty() {
+        return remove_if_exists(&path);
+    }
+
+    let mut contents =
+        String::from("# Public keys this vault is encrypted to, see 'r-auth recipient'\n");
+    for recipient in recipients {
+        contents.push_str(&recipient.to_string());
+        contents.push('\n');
+    }
+    replace_atomic(&path, contents.as_bytes())
+}
+
+/// Both generations of an accounts database and its recipients list as found on disk,
+/// so that they can be put back after a multi-step change fails
+pub struct Generations {
+    pub current: Option<Vec<u8>>,
+    pub backup: Option<Vec<u8>>,
+    pub recipients: Option<Vec<u8>>,
+}
+
+impl Generations {
+    pub fn read(storage_file: &str) -> Result<Self> {
+        Ok(Self {
+            current: read_if_exists(Path::new(storage_file))?,
+            backup: read_if_exists(&backup_path(storage_file))?,
+            recipients: read_if_exists(&recipients_path(storage_file))?,
+        })
+    }
+
+    /// Rewrites every file as it was read, deleting those that did not exist
+    pub fn restore(&self, storage_file: &str) -> Result<()> {
+        for (path, contents) in [
+            (PathBuf::from(storage_file), &self.current),
+            (backup_path(storage_file), &self.backup),
+            (recipients_path(storage_file), &self.recipients),
+        ] {
+            match contents {
+                Some(contents) => replace_atomic(&path, contents)?,
+                None => remove_if_exists(&path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(AuthError::StorageFile(format!(
+            "Failed to delete {}: {}",
+            path.display(),
+            e
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AuthError::StorageFile(format!(
+            "Failed to read {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Location of the lock file guarding `storage_file`
+/// The database itself cannot be locked because [`write_atomic`] replaces it
+pub fn lock_path(storage_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.lock", storage_file))
+}
+
+/// Exclusive advisory lock on the accounts database, released when dropped
+pub struct StorageLock {
+    _file: File,
+}
+
+impl StorageLock {
+    /// Waits up to `timeout` for other processes to release the database
+    pub fn acquire(storage_file: &str, timeout: Duration) -> Result<Self> {
+        let path = lock_path(storage_file);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| AuthError::StorageFile(format!("Failed to open lock file: {}", e)))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(e) if e.kind() == fs4::lock_contended_error().kind() => {
+                    if started.elapsed() >= timeout {
+                        return Err(AuthError::StorageLocked(path.display().to_string()));
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => {
+                    return Err(AuthError::StorageFile(format!(
+                        "Failed to lock storage: {}",
+                        e
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Location of the ciphertext preserved before migrating an unversioned store
+pub fn legacy_backup_path(storage_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.v0.bak", storage_file))
+}
+
+/// Preserves the pre-migration ciphertext; an existing backup is never overwritten
+pub fn write_legacy_backup(storage_file: &str, encrypted: &[u8]) -> Result<()> {
+    let path = legacy_backup_path(storage_file);
+    if path.exists() {
+        return Ok(());
+    }
+    replace_atomic(&path, encrypted)
+}
+
+/// Location a database that can no longer be decrypted is set aside to by recovery
+pub fn corrupt_path(storage_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.corrupt", storage_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::OtpKind;
+    use crate::totp::Algorithm;
+
+    #[test]
+    fn migrates_name_to_secret_map() {
+        let decoded = decode(r#"{"GitHub": "JBSWY3DPEHPK3PXP"}"#).unwrap();
+        assert!(decoded.migrated);
+
+        let account = &decoded.accounts["GitHub"];
+        assert_eq!(account.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(account.kind, OtpKind::Totp);
+        assert_eq!(account.algorithm, Algorithm::Sha1);
+        assert_eq!(account.digits, 6);
+        assert_eq!(account.period, 30);
+        assert_eq!(account.created_at, None);
+    }
+
+    #[test]
+    fn versioned_stores_are_never_migrated() {
+        let unknown = decode(r#"{"version": "2", "accounts": {}}"#);
+        assert!(
+            matches!(unknown, Err(AuthError::InvalidStorage(e)) if e.contains("unknown variant"))
+        );
+
+        let damaged = decode(r#"{"version": "1", "accounts": {"GitHub": {"secret": 42}}}"#);
+        assert!(
+            matches!(damaged, Err(AuthError::InvalidStorage(e)) if e.starts_with("Invalid store"))
+        );
+
+        assert!(decode(r#"{"AWS": {"secret": "JBSWY3DPEHPK3PXP"}}"#).is_err());
+    }
+
+    #[test]
+    fn migrated_store_round_trips_as_current_version() {
+        let migrated = decode(r#"{"GitHub": "JBSWY3DPEHPK3PXP"}"#).unwrap();
+        let encoded = encode(&migrated.accounts).unwrap();
+        assert!(encoded.contains(r#""version": "1""#));
+
+        let decoded = decode(&encoded).unwrap();
+        assert!(!decoded.migrated);
+        assert_eq!(decoded.accounts, migrated.accounts);
+    }
+
+    #[test]
+    fn empty_legacy_store_migrates() {
+        let decoded = decode("{}").unwrap();
+        assert!(decoded.migrated);
+        assert!(decoded.accounts.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        assert!(decode("not json").is_err());
+        assert!(decode(r#"{"GitHub": 42}"#).is_err());
+    }
+
+    #[test]
+    fn atomic_write_keeps_previous_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_file = dir.path().join("accounts.json");
+        let storage_file = storage_file.to_str().unwrap();
+
+        write_atomic(storage_file, b"first").unwrap();
+        assert_eq!(std::fs::read(storage_file).unwrap(), b"first");
+        assert!(!backup_path(storage_file).exists());
+
+        write_atomic(storage_file, b"second").unwrap();
+        write_atomic(storage_file, b"third").unwrap();
+        assert_eq!(std::fs::read(storage_file).unwrap(), b"third");
+        assert_eq!(std::fs::read(backup_path(storage_file)).unwrap(), b"second");
+
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    #[test]
+    fn legacy_backup_is_written_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_file = dir.path().join("accounts.json");
+        let storage_file = storage_file.to_str().unwrap();
+
+        write_legacy_backup(storage_file, b"original").unwrap();
+        write_legacy_backup(storage_file, b"second").unwrap();
+
+        let backup = std::fs::read(legacy_backup_path(storage_file)).unwrap();
+        assert_eq!(backup, b"original");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(legacy_backup_path(storage_file))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -1,17 +1,89 @@
+//! Time-based one-time passwords and otpauth URIs
+
 use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::fmt;
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
 use url::Url;
 
+use crate::account::OtpKind;
 use crate::error::{AuthError, Result};
+use crate::hotp;
 
 type HmacSha1 = Hmac<Sha1>;
+type HmacSha256 = Hmac<Sha256>;
+type HmacSha512 = Hmac<Sha512>;
+
+/// HMAC hash function used to derive codes
+/// SHA-256 and SHA-512 are allowed by [RFC 6238 Section 1.2](https://datatracker.ietf.org/doc/html/rfc6238#section-1.2)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Computes the HMAC of `message` keyed with `secret`
+    pub(crate) fn hmac(&self, secret: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+        let map_err = |e: hmac::digest::InvalidLength| AuthError::InvalidSecret(e.to_string());
+        Ok(match self {
+            Algorithm::Sha1 => {
+                let mut mac = HmacSha1::new_from_slice(secret).map_err(map_err)?;
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha256 => {
+                let mut mac = HmacSha256::new_from_slice(secret).map_err(map_err)?;
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut mac = HmacSha512::new_from_slice(secret).map_err(map_err)?;
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        })
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(Algorithm::Sha1),
+            "SHA256" => Ok(Algorithm::Sha256),
+            "SHA512" => Ok(Algorithm::Sha512),
+            _ => Err(AuthError::InvalidAlgorithm(s.to_string())),
+        }
+    }
+}
 
 /// TOTP implementation based on:
 /// - [RFC 6238 - TOTP: Time-Based One-Time Password Algorithm](https://datatracker.ietf.org/doc/html/rfc6238)
 /// - [RFC 4226 - HOTP: An HMAC-Based One-Time Password Algorithm](https://datatracker.ietf.org/doc/html/rfc4226)
 pub struct TOTP {
     secret: Vec<u8>,
+    algorithm: Algorithm,
     digits: u32,
     interval: u64,
 }
@@ -25,58 +97,99 @@ impl TOTP {
 
         Ok(Self {
             secret,
+            algorithm: Algorithm::Sha1,
             digits: 6,
             interval: 30,
         })
     }
 
+    /// Sets the HMAC algorithm used to generate codes
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the number of digits in generated codes
+    pub fn with_digits(mut self, digits: u32) -> Result<Self> {
+        hotp::validate_digits(digits)?;
+        self.digits = digits;
+        Ok(self)
+    }
+
+    /// Sets the time step in seconds (X in [RFC 6238 Section 4.1](https://datatracker.ietf.org/doc/html/rfc6238#section-4.1))
+    pub fn with_period(mut self, period: u64) -> Result<Self> {
+        if period == 0 {
+            return Err(AuthError::InvalidParameter(
+                "period must be greater than zero".into(),
+            ));
+        }
+        self.interval = period;
+        Ok(self)
+    }
+
+    /// Current Unix time in seconds
+    pub fn unix_time() -> Result<u64> {
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| AuthError::InvalidSecret(e.to_string()))?
+            .as_secs())
+    }
+
     /// Generates current TOTP code based on current Unix timestamp
     /// As specified in [RFC 6238 Section 4](https://datatracker.ietf.org/doc/html/rfc6238#section-4)
     pub fn now(&self) -> Result<String> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map_err(|e| AuthError::InvalidSecret(e.to_string()))?
-            .as_secs();
-        self.generate(timestamp)
+        self.generate(Self::unix_time()?)
+    }
+
+    /// Seconds from `timestamp` until the code generated for it expires
+    pub fn remaining_at(&self, timestamp: u64) -> u64 {
+        self.interval - timestamp % self.interval
     }
 
     /// Generates TOTP code for a given timestamp
     /// Implementation follows [RFC 6238 Section 4.2](https://datatracker.ietf.org/doc/html/rfc6238#section-4.2)
-    fn generate(&self, timestamp: u64) -> Result<String> {
-        // HMAC-SHA1 is used as specified in RFC 6238
+    pub fn generate(&self, timestamp: u64) -> Result<String> {
         let counter = timestamp / self.interval;
-        let counter_bytes = counter.to_be_bytes();
-
-        let mut mac = HmacSha1::new_from_slice(&self.secret)
-            .map_err(|e| AuthError::InvalidSecret(e.to_string()))?;
-        mac.update(&counter_bytes);
-        let result = mac.finalize();
-        let code_bytes = result.into_bytes();
+        hotp::generate(&self.secret, self.algorithm, self.digits, counter)
+    }
 
-        // Dynamic truncation as specified in RFC 4226 Section 5.4
-        let offset = (code_bytes[19] & 0xf) as usize;
-        let code = ((code_bytes[offset] & 0x7f) as u32) << 24
-            | (code_bytes[offset + 1] as u32) << 16
-            | (code_bytes[offset + 2] as u32) << 8
-            | (code_bytes[offset + 3] as u32);
+    /// Checks `code` against the time steps within `skew_steps` of `timestamp`, allowing
+    /// for clock drift as recommended by [RFC 6238 Section 5.2](https://datatracker.ietf.org/doc/html/rfc6238#section-5.2)
+    /// Every step in the window is compared in constant time, so response times reveal
+    /// nothing about a guess. Returns the offset in steps of the matching code, the one
+    /// closest to `timestamp` if several match
+    pub fn verify(&self, code: &str, timestamp: u64, skew_steps: u32) -> Result<Option<i64>> {
+        let step = timestamp / self.interval;
+        let skew = i64::from(skew_steps);
 
-        let code = code % 10u32.pow(self.digits);
-        Ok(format!("{:0width$}", code, width = self.digits as usize))
+        let mut matched: Option<i64> = None;
+        for offset in -skew..=skew {
+            let Some(counter) = step.checked_add_signed(offset) else {
+                continue;
+            };
+            let candidate = hotp::generate(&self.secret, self.algorithm, self.digits, counter)?;
+            let equal = bool::from(candidate.as_bytes().ct_eq(code.as_bytes()));
+            if equal && matched.is_none_or(|best| offset.abs() < best.abs()) {
+                matched = Some(offset);
+            }
+        }
+        Ok(matched)
     }
 
     /// Generates an otpauth URI for QR code generation
     /// Format follows Google Authenticator's KeyUriFormat:
-    /// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+    /// <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>
     pub fn provisioning_uri(&self, name: &str, issuer: &str) -> String {
         // Base32 encoding as specified in RFC 4648
         let secret = base32::encode(base32::Alphabet::RFC4648 { padding: true }, &self.secret);
 
         let mut url = Url::parse("otpauth://totp/").unwrap();
-        url.set_path(&format!("{}", name));
+        url.set_path(name);
 
         {
             let mut pairs = url.query_pairs_mut();
             pairs.append_pair("secret", &secret);
+            pairs.append_pair("algorithm", &self.algorithm.to_string());
             pairs.append_pair("digits", &self.digits.to_string());
             pairs.append_pair("period", &self.interval.to_string());
             pairs.append_pair("issuer", issuer);
@@ -85,3 +198,310 @@ impl TOTP {
         url.to_string()
     }
 }
+
+/// Decodes a Base32 secret in any of the forms issuers hand out (lowercase,
+/// unpadded, grouped with spaces) and returns it in canonical padded form
+pub fn normalize_secret(secret: &str) -> Result<String> {
+    let cleaned: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    if cleaned.is_empty() {
+        return Err(AuthError::InvalidSecret("secret is empty".into()));
+    }
+
+    let bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &cleaned)
+        .ok_or(AuthError::Base32DecodeError)?;
+    Ok(base32::encode(
+        base32::Alphabet::RFC4648 { padding: true },
+        &bytes,
+    ))
+}
+
+/// Splits an `Issuer:account` label into its issuer prefix and account name
+pub(crate) fn split_label(label: &str) -> (Option<String>, String) {
+    match label.split_once(':') {
+        Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+        None => (None, label.trim().to_string()),
+    }
+}
+
+/// Account parameters decoded from an `otpauth://` URI
+/// Format follows Google Authenticator's KeyUriFormat:
+/// <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpauthUri {
+    pub kind: OtpKind,
+    /// Account part of the label, without the issuer prefix
+    pub account: String,
+    pub issuer: Option<String>,
+    /// Secret in canonical padded Base32
+    pub secret: String,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    pub counter: u64,
+}
+
+impl OtpauthUri {
+    /// Parses an `otpauth://totp/...` or `otpauth://hotp/...` URI
+    pub fn parse(uri: &str) -> Result<Self> {
+        let invalid = |msg: String| AuthError::InvalidUri(msg);
+
+        let url = Url::parse(uri.trim()).map_err(|e| invalid(e.to_string()))?;
+        if url.scheme() != "otpauth" {
+            return Err(invalid(format!(
+                "expected otpauth:// scheme, got {}://",
+                url.scheme()
+            )));
+        }
+
+        let kind = match url.host_str() {
+            Some(host) => host
+                .parse::<OtpKind>()
+                .map_err(|_| invalid(format!("unsupported type '{}'", host)))?,
+            None => return Err(invalid("missing type".into())),
+        };
+
+        let label = percent_decode_str(url.path().trim_start_matches('/'))
+            .decode_utf8()
+            .map_err(|e| invalid(format!("label is not valid UTF-8: {}", e)))?
+            .into_owned();
+        let (label_issuer, account) = split_label(&label);
+        if account.is_empty() {
+            return Err(invalid("missing account name in label".into()));
+        }
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut algorithm = Algorithm::default();
+        let mut digits = 6;
+        let mut period = 30;
+        let mut counter = None;
+
+        for (key, value) in url.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "issuer" => issuer = Some(value.trim().to_string()),
+                "algorithm" => algorithm = value.parse()?,
+                "digits" => {
+                    digits = value
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid digits '{}'", value)))?
+                }
+                "period" => {
+                    period = value
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid period '{}'", value)))?
+                }
+                "counter" => {
+                    counter = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid(format!("invalid counter '{}'", value)))?,
+                    )
+                }
+                // Unknown parameters (e.g. image) are ignored as the format allows
+                _ => {}
+            }
+        }
+
+        let secret = secret.ok_or_else(|| invalid("missing secret parameter".into()))?;
+        if kind == OtpKind::Hotp && counter.is_none() {
+            return Err(invalid("hotp URI is missing the counter parameter".into()));
+        }
+
+        // Validate parameters the same way stored accounts are
+        hotp::validate_digits(digits)?;
+        if period == 0 {
+            return Err(AuthError::InvalidParameter(
+                "period must be greater than zero".into(),
+            ));
+        }
+
+        Ok(Self {
+            kind,
+            account,
+            issuer: issuer.filter(|i| !i.is_empty()).or(label_issuer),
+            secret,
+            algorithm,
+            digits,
+            period,
+            counter: counter.unwrap_or(0),
+        })
+    }
+
+    /// Name under which the account is stored, in `Issuer:account` form when an issuer is known
+    pub fn name(&self) -> String {
+        match &self.issuer {
+            Some(issuer) if !issuer.is_empty() => format!("{}:{}", issuer, self.account),
+            _ => self.account.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a TOTP with the raw ASCII seed used by RFC 6238 Appendix B
+    fn rfc_totp(seed: &[u8], algorithm: Algorithm) -> TOTP {
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: true }, seed);
+        TOTP::new(&secret)
+            .unwrap()
+            .with_algorithm(algorithm)
+            .with_digits(8)
+            .unwrap()
+    }
+
+    // Test vectors from RFC 6238 Appendix B
+    const VECTORS: [(u64, &str, &str, &str); 6] = [
+        (59, "94287082", "46119246", "90693936"),
+        (1111111109, "07081804", "68084774", "25091201"),
+        (1111111111, "14050471", "67062674", "99943326"),
+        (1234567890, "89005924", "91819424", "93441116"),
+        (2000000000, "69279037", "90698825", "38618901"),
+        (20000000000, "65353130", "77737706", "47863826"),
+    ];
+
+    #[test]
+    fn rfc6238_sha1() {
+        let totp = rfc_totp(b"12345678901234567890", Algorithm::Sha1);
+        for (time, expected, _, _) in VECTORS {
+            assert_eq!(totp.generate(time).unwrap(), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn rfc6238_sha256() {
+        let totp = rfc_totp(b"12345678901234567890123456789012", Algorithm::Sha256);
+        for (time, _, expected, _) in VECTORS {
+            assert_eq!(totp.generate(time).unwrap(), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn rfc6238_sha512() {
+        let totp = rfc_totp(
+            b"1234567890123456789012345678901234567890123456789012345678901234",
+            Algorithm::Sha512,
+        );
+        for (time, _, _, expected) in VECTORS {
+            assert_eq!(totp.generate(time).unwrap(), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_accepts_codes_within_skew() {
+        let totp = rfc_totp(b"12345678901234567890", Algorithm::Sha1);
+
+        // "07081804" belongs to T = 1111111109, the step before T = 1111111111
+        assert_eq!(totp.verify("14050471", 1111111111, 0).unwrap(), Some(0));
+        assert_eq!(totp.verify("07081804", 1111111111, 0).unwrap(), None);
+        assert_eq!(totp.verify("07081804", 1111111111, 1).unwrap(), Some(-1));
+        assert_eq!(totp.verify("14050471", 1111111081, 1).unwrap(), Some(1));
+
+        assert_eq!(totp.verify("00000000", 1111111111, 2).unwrap(), None);
+        assert_eq!(totp.verify("1405047", 1111111111, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn verify_window_stops_at_the_epoch() {
+        let totp = rfc_totp(b"12345678901234567890", Algorithm::Sha1);
+        assert_eq!(totp.verify("94287082", 59, 5).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn provisioning_uri_includes_algorithm() {
+        let totp = TOTP::new("JBSWY3DPEHPK3PXP")
+            .unwrap()
+            .with_algorithm(Algorithm::Sha256);
+        let uri = totp.provisioning_uri("GitHub", "CLI Authenticator");
+        assert!(uri.contains("algorithm=SHA256"));
+    }
+
+    #[test]
+    fn custom_digits_and_period() {
+        let totp = TOTP::new("JBSWY3DPEHPK3PXP")
+            .unwrap()
+            .with_digits(8)
+            .unwrap()
+            .with_period(60)
+            .unwrap();
+        assert_eq!(totp.generate(59).unwrap(), totp.generate(0).unwrap());
+        assert_ne!(totp.generate(60).unwrap(), totp.generate(0).unwrap());
+        assert_eq!(totp.generate(0).unwrap().len(), 8);
+
+        let uri = totp.provisioning_uri("Vendor", "CLI Authenticator");
+        assert!(uri.contains("digits=8"));
+        assert!(uri.contains("period=60"));
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let totp = || TOTP::new("JBSWY3DPEHPK3PXP").unwrap();
+        assert!(totp().with_digits(5).is_err());
+        assert!(totp().with_digits(9).is_err());
+        assert!(totp().with_period(0).is_err());
+    }
+
+    #[test]
+    fn parses_totp_uri() {
+        let uri = OtpauthUri::parse(
+            "otpauth://totp/ACME%20Co:john.doe@email.com?secret=hxdmvjecjjwsrb3hwizr4ifugftmxboz&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(uri.kind, OtpKind::Totp);
+        assert_eq!(uri.account, "john.doe@email.com");
+        assert_eq!(uri.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(uri.secret, "HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ");
+        assert_eq!(uri.algorithm, Algorithm::Sha256);
+        assert_eq!(uri.digits, 8);
+        assert_eq!(uri.period, 60);
+        assert_eq!(uri.name(), "ACME Co:john.doe@email.com");
+    }
+
+    #[test]
+    fn parses_hotp_uri_with_defaults() {
+        let uri =
+            OtpauthUri::parse("otpauth://hotp/VPN?secret=JBSWY3DPEHPK3PXP&counter=42").unwrap();
+        assert_eq!(uri.kind, OtpKind::Hotp);
+        assert_eq!(uri.counter, 42);
+        assert_eq!(uri.algorithm, Algorithm::Sha1);
+        assert_eq!(uri.digits, 6);
+        assert_eq!(uri.issuer, None);
+        assert_eq!(uri.name(), "VPN");
+    }
+
+    #[test]
+    fn provisioning_uri_round_trips() {
+        let totp = TOTP::new("JBSWY3DPEHPK3PXP")
+            .unwrap()
+            .with_algorithm(Algorithm::Sha512)
+            .with_digits(7)
+            .unwrap();
+        let uri = OtpauthUri::parse(&totp.provisioning_uri("alice", "GitHub")).unwrap();
+        assert_eq!(uri.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(uri.algorithm, Algorithm::Sha512);
+        assert_eq!(uri.digits, 7);
+        assert_eq!(uri.name(), "GitHub:alice");
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        for uri in [
+            "https://totp/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://motp/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/x",
+            "otpauth://totp/?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/x?secret=not-base32!",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=12",
+            "otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP",
+        ] {
+            assert!(OtpauthUri::parse(uri).is_err(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Interactive full-screen view of the vault's codes, with search and copy
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::io::{stdout, IsTerminal, Write};
+use std::panic;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clipboard::{self, ClipboardBackend};
+use r_auth::account::OtpKind;
+use r_auth::authenticator::TOTPAuthenticator;
+use r_auth::error::Result;
+use r_auth::totp::TOTP;
+
+/// How often the screen is redrawn when no key is pressed
+const TICK: Duration = Duration::from_millis(250);
+/// Width of the validity bar, in cells
+const BAR_WIDTH: usize = 20;
+/// Lines taken by the header and the status line
+const CHROME_LINES: usize = 3;
+
+/// Clipboard settings used when a code is copied with Enter
+pub struct CopySettings {
+    pub backend: ClipboardBackend,
+    /// Seconds before the code is cleared, 0 to keep it
+    pub clear_after: u64,
+}
+
+/// Interactive live view of all codes
+///
+/// Keys: arrows or j/k to move, PageUp/PageDown, Home/End, `/` to search,
+/// Enter to copy the selected code, `q` or Esc to quit.
+/// Falls back to printing the codes once when stdout is not a terminal
+pub fn show(authenticator: &mut TOTPAuthenticator, copy: CopySettings) -> Result<()> {
+    if !stdout().is_terminal() {
+        return print_once(authenticator);
+    }
+
+    let _terminal = TerminalGuard::enter()?;
+    let mut view = View::new(authenticator.list_accounts());
+
+    loop {
+        let (width, height) = terminal::size()?;
+        view.resize(usize::from(height).saturating_sub(CHROME_LINES));
+        draw(authenticator, &view, usize::from(width))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+
+        match view.handle_key(key) {
+            Action::None => {}
+            Action::Quit => return Ok(()),
+            Action::Copy(name) => {
+                view.status = Some(match copy_code(authenticator, &name, &copy) {
+                    Ok(()) => format!("Copied code for {}", name),
+                    Err(e) => format!("Copy failed: {}", e),
+                });
+            }
+        }
+    }
+}
+
+fn print_once(authenticator: &TOTPAuthenticator) -> Result<()> {
+    for (name, code) in authenticator.current_codes()? {
+        println!(
+            "{}: {} (refreshing in {}s)",
+            name,
+            code.code,
+            code.remaining_seconds.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn copy_code(authenticator: &mut TOTPAuthenticator, name: &str, copy: &CopySettings) -> Result<()> {
+    let Some(code) = authenticator.get_code(name)? else {
+        return Ok(());
+    };
+    copy.backend.open().set(&code.code)?;
+    if copy.clear_after > 0 {
+        clipboard::clear_later(
+            copy.backend,
+            &code.code,
+            Duration::from_secs(copy.clear_after),
+        )?;
+    }
+    Ok(())
+}
+
+type PanicHook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Raw mode and the alternate screen, undone when dropped or on panic
+struct TerminalGuard {
+    /// The panic hook installed before ours, put back when the guard is dropped
+    previous_hook: Option<Arc<PanicHook>>,
+}
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, cursor::Hide)?;
+
+        // Unwinding drops the guard, but the panic message is printed before that and
+        // would be garbled by raw mode
+        let previous = Arc::new(panic::take_hook());
+        let hook = Arc::clone(&previous);
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+        Ok(Self {
+            previous_hook: Some(previous),
+        })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+        // The hook cannot be changed while panicking, and ours still defers to it
+        if std::thread::panicking() {
+            return;
+        }
+        drop(panic::take_hook());
+        if let Some(previous) = self.previous_hook.take() {
+            match Arc::try_unwrap(previous) {
+                Ok(hook) => panic::set_hook(hook),
+                Err(hook) => panic::set_hook(Box::new(move |info| hook(info))),
+            }
+        }
+    }
+}
+
+fn restore() {
+    let _ = execute!(stdout(), cursor::Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+fn draw(authenticator: &TOTPAuthenticator, view: &View, width: usize) -> Result<()> {
+    let mut out = stdout().lock();
+    let now = TOTP::unix_time()?;
+    let accounts = authenticator.accounts();
+    let name_width = view
+        .visible()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(width / 2);
+
+    // Lines are overwritten in place rather than clearing the screen, which flickers
+    queue!(out, cursor::MoveTo(0, 0))?;
+    let title = match (&view.search, view.filter.is_empty()) {
+        (Some(query), _) => format!("Search: {}_", query),
+        (None, false) => format!("Codes matching '{}'", view.filter),
+        (None, true) => "Current codes".to_string(),
+    };
+    queue!(
+        out,
+        Print(truncate(&title, width)),
+        Clear(ClearType::UntilNewLine),
+        cursor::MoveToNextLine(2)
+    )?;
+
+    for (row, name) in view.visible().enumerate() {
+        let line = match accounts.get(name) {
+            Some(account) if account.kind == OtpKind::Hotp => format!(
+                "{:<name_width$}  {}",
+                truncate(name, name_width),
+                "(counter-based, Enter to generate and copy)"
+            ),
+            Some(account) => match account.totp() {
+                Ok(totp) => format!(
+                    "{:<name_width$}  {}  {} {:>2}s",
+                    truncate(name, name_width),
+                    totp.generate(now)?,
+                    progress_bar(totp.remaining_at(now), account.period),
+                    totp.remaining_at(now)
+                ),
+                Err(e) => format!("{:<name_width$}  {}", truncate(name, name_width), e),
+            },
+            None => continue,
+        };
+
+        let selected = view.offset + row == view.selected;
+        if selected {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        queue!(out, Print(truncate(&line, width)))?;
+        if selected {
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+        queue!(
+            out,
+            Clear(ClearType::UntilNewLine),
+            cursor::MoveToNextLine(1)
+        )?;
+    }
+    if view.matches.is_empty() {
+        queue!(out, Print("No matching accounts"))?;
+    }
+    queue!(out, Clear(ClearType::FromCursorDown))?;
+
+    let status = view
+        .status
+        .clone()
+        .unwrap_or_else(|| "↑/↓ move  / search  Enter copy  q quit".to_string());
+    let (_, height) = terminal::size()?;
+    queue!(
+        out,
+        cursor::MoveTo(0, height.saturating_sub(1)),
+        Print(truncate(&status, width)),
+        Clear(ClearType::UntilNewLine)
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Bar whose filled part shrinks as the code nears expiry
+fn progress_bar(remaining: u64, period: u64) -> String {
+    let filled = (remaining as usize * BAR_WIDTH).div_ceil(period.max(1) as usize);
+    let filled = filled.min(BAR_WIDTH);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    None,
+    Quit,
+    Copy(String),
+}
+
+/// Selection, scrolling and search state of the live view
+struct View {
+    /// All account names, sorted
+    names: Vec<String>,
+    /// Indices into `names` matching the filter
+    matches: Vec<usize>,
+    filter: String,
+    /// Query being typed after `/`
+    search: Option<String>,
+    /// Index into `matches`
+    selected: usize,
+    /// First visible index into `matches`
+    offset: usize,
+    rows: usize,
+    status: Option<String>,
+}
+
+impl View {
+    fn new(mut names: Vec<String>) -> Self {
+        names.sort_by_key(|name| name.to_lowercase());
+        let matches = (0..names.len()).collect();
+        Self {
+            names,
+            matches,
+            filter: String::new(),
+            search: None,
+            selected: 0,
+            offset: 0,
+            rows: 1,
+            status: None,
+        }
+    }
+
+    fn visible(&self) -> impl Iterator<Item = &str> {
+        self.matches
+            .iter()
+            .skip(self.offset)
+            .take(self.rows)
+            .map(|&i| self.names[i].as_str())
+    }
+
+    fn resize(&mut self, rows: usize) {
+        self.rows = rows.max(1);
+        self.scroll();
+    }
+
+    fn set_filter(&mut self, filter: &str) {
+        let needle = filter.to_lowercase();
+        self.filter = filter.to_string();
+        self.matches = (0..self.names.len())
+            .filter(|&i| self.names[i].to_lowercase().contains(&needle))
+            .collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    /// Moves the selection by `delta` rows, stopping at either end
+    fn move_by(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        self.scroll();
+    }
+
+    /// Keeps the selection on screen
+    fn scroll(&mut self) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.rows {
+            self.offset = self.selected + 1 - self.rows;
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        self.status = None;
+
+        if let Some(query) = &mut self.search {
+            match key.code {
+                KeyCode::Char(c) => query.push(c),
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Enter => {
+                    self.search = None;
+                    return Action::None;
+                }
+                KeyCode::Esc => {
+                    self.search = None;
+                    self.set_filter("");
+                    return Action::None;
+                }
+                _ => return Action::None,
+            }
+            let query = query.clone();
+            self.set_filter(&query);
+            return Action::None;
+        }
+
+        let page = self.rows as isize;
+        match key.code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Esc if self.filter.is_empty() => return Action::Quit,
+            KeyCode::Esc => self.set_filter(""),
+            KeyCode::Char('/') => self.search = Some(String::new()),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-page),
+            KeyCode::PageDown => self.move_by(page),
+            KeyCode::Home => self.move_by(isize::MIN),
+            KeyCode::End => self.move_by(isize::MAX),
+            KeyCode::Enter => {
+                if let Some(&i) = self.matches.get(self.selected) {
+                    return Action::Copy(self.names[i].clone());
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn view(names: &[&str], rows: usize) -> View {
+        let mut view = View::new(names.iter().map(|name| name.to_string()).collect());
+        view.resize(rows);
+        view
+    }
+
+    #[test]
+    fn sorts_case_insensitively() {
+        let view = view(&["github", "AWS", "Bank"], 10);
+        assert_eq!(
+            view.visible().collect::<Vec<_>>(),
+            ["AWS", "Bank", "github"]
+        );
+    }
+
+    #[test]
+    fn scrolls_to_keep_selection_visible() {
+        let mut view = view(&["a", "b", "c", "d", "e"], 2);
+        view.handle_key(key(KeyCode::Down));
+        view.handle_key(key(KeyCode::Down));
+        assert_eq!(view.selected, 2);
+        assert_eq!(view.visible().collect::<Vec<_>>(), ["b", "c"]);
+
+        view.handle_key(key(KeyCode::End));
+        assert_eq!(view.visible().collect::<Vec<_>>(), ["d", "e"]);
+        view.handle_key(key(KeyCode::Down));
+        assert_eq!(view.selected, 4);
+
+        view.handle_key(key(KeyCode::Home));
+        assert_eq!(view.visible().collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn search_filters_incrementally() {
+        let mut view = view(&["GitHub", "GitLab", "AWS"], 10);
+        view.handle_key(key(KeyCode::Char('/')));
+        for c in "git".chars() {
+            view.handle_key(key(KeyCode::Char(c)));
+        }
+        assert_eq!(view.visible().count(), 2);
+        view.handle_key(key(KeyCode::Char('h')));
+        assert_eq!(view.visible().collect::<Vec<_>>(), ["GitHub"]);
+
+        // Typed keys go to the query, not to the key bindings
+        view.handle_key(key(KeyCode::Char('q')));
+        assert_eq!(view.search.as_deref(), Some("githq"));
+        view.handle_key(key(KeyCode::Backspace));
+
+        view.handle_key(key(KeyCode::Enter));
+        assert_eq!(
+            view.handle_key(key(KeyCode::Enter)),
+            Action::Copy("GitHub".into())
+        );
+
+        view.handle_key(key(KeyCode::Esc));
+        assert_eq!(view.visible().count(), 3);
+        assert_eq!(view.handle_key(key(KeyCode::Char('q'))), Action::Quit);
+    }
+
+    #[test]
+    fn progress_bar_shrinks_with_remaining_time() {
+        assert_eq!(progress_bar(30, 30), format!("[{}]", "#".repeat(BAR_WIDTH)));
+        assert_eq!(
+            progress_bar(15, 30),
+            format!("[{}{}]", "#".repeat(10), "-".repeat(10))
+        );
+        assert_eq!(progress_bar(1, 30), format!("[#{}]", "-".repeat(19)));
+    }
+}
@@ -0,0 +1,366 @@
+//! Locations of accounts vaults: the default one, named vaults kept side by side in
+//! the config directory, and vaults at an arbitrary path
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::crypto::KeySlot;
+use crate::error::{AuthError, Result};
+
+/// File holding the accounts of a vault, inside its directory
+const ACCOUNTS_FILE: &str = "accounts.json";
+
+/// Directory of named vaults, inside the config directory
+const VAULTS_DIR: &str = "vaults";
+
+/// File describing a named vault, inside its directory
+const METADATA_FILE: &str = "vault.json";
+
+/// Settings of a vault, stored next to its accounts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Metadata {
+    /// Whether a named vault is encrypted with its own key rather than the shared one
+    #[serde(default)]
+    own_key: bool,
+    /// The user's key file used as the key kept in this directory, see
+    /// [`KeyMode::KeyFile`](crate::crypto::KeyMode::KeyFile)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_file: Option<PathBuf>,
+}
+
+impl Metadata {
+    /// Reads the metadata in `dir`, `None` if there is none
+    fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(METADATA_FILE);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&contents).map(Some).map_err(|e| {
+            AuthError::InvalidStorage(format!("Invalid metadata in {}: {}", path.display(), e))
+        })
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        crate::storage::replace_atomic(&dir.join(METADATA_FILE), &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// An accounts database and the key it is encrypted with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vault {
+    /// Name of a named vault, `None` for the default vault or one given by path
+    pub name: Option<String>,
+    /// The accounts database
+    pub path: PathBuf,
+    /// Where the vault's encryption key is stored
+    pub key_slot: KeySlot,
+    /// Whether the key belongs to this vault alone, so that deleting the vault may
+    /// delete the key
+    pub own_key: bool,
+}
+
+impl Vault {
+    /// The vault at `path`, encrypted with the shared key found in `vaults`
+    pub fn at_path(vaults: &Vaults, path: PathBuf) -> Self {
+        Self {
+            name: None,
+            path,
+            key_slot: vaults.shared_key_slot(),
+            own_key: false,
+        }
+    }
+}
+
+/// The r-auth config directory, home of the default vault and the config file
+pub fn config_root() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(AuthError::ConfigDir)?
+        .join("r-auth"))
+}
+
+/// The vaults kept in the r-auth config directory
+pub struct Vaults {
+    root: PathBuf,
+    /// Key file of the shared key, from the default vault's metadata
+    shared_key_file: Option<PathBuf>,
+}
+
+impl Vaults {
+    /// Vaults in the r-auth config directory, which is created if needed
+    pub fn new() -> Result<Self> {
+        Self::at(config_root()?)
+    }
+
+    /// Vaults kept in `root` instead of the config directory, which is created if needed
+    pub fn at(root: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&root).map_err(|e| {
+            AuthError::StorageFile(format!("Failed to create config directory: {}", e))
+        })?;
+        let shared_key_file = Metadata::read(&root)?.and_then(|metadata| metadata.key_file);
+        Ok(Self {
+            root,
+            shared_key_file,
+        })
+    }
+
+    /// The key used by the default vault and by named vaults without a key of their own
+    pub fn shared_key_slot(&self) -> KeySlot {
+        KeySlot::shared(&self.root).with_key_file(self.shared_key_file.clone())
+    }
+
+    /// The vault used when none is selected
+    pub fn default_vault(&self) -> Vault {
+        Vault {
+            name: None,
+            path: self.root.join(ACCOUNTS_FILE),
+            key_slot: self.shared_key_slot(),
+            own_key: true,
+        }
+    }
+
+    fn dir(&self, name: &str) -> PathBuf {
+        self.root.join(VAULTS_DIR).join(name)
+    }
+
+    fn vault(&self, name: &str, metadata: Metadata) -> Vault {
+        let dir = self.dir(name);
+        let key_slot = if metadata.own_key {
+            KeySlot::named(name, &dir).with_key_file(metadata.key_file)
+        } else {
+            self.shared_key_slot()
+        };
+        Vault {
+            name: Some(name.to_string()),
+            path: dir.join(ACCOUNTS_FILE),
+            key_slot,
+            own_key: metadata.own_key,
+        }
+    }
+
+    /// The named vault `name`, which must have been created with [`Vaults::create`]
+    pub fn get(&self, name: &str) -> Result<Vault> {
+        validate_name(name)?;
+        let metadata = Metadata::read(&self.dir(name))?
+            .ok_or_else(|| AuthError::VaultNotFound(name.to_string()))?;
+        Ok(self.vault(name, metadata))
+    }
+
+    /// Records in the metadata of the vault `key_slot` belongs to that its key is
+    /// `key_file`, as returned by [`Crypto::init`](crate::crypto::Crypto::init), or that
+    /// it is a generated one with `None`
+    pub fn record_key_file(&self, key_slot: &KeySlot, key_file: Option<&Path>) -> Result<()> {
+        let dir = key_slot.dir();
+        let mut metadata = Metadata::read(dir)?.unwrap_or_default();
+        metadata.key_file = key_file.map(Path::to_path_buf);
+        metadata.write(dir)
+    }
+
+    /// Creates the named vault `name`, empty until accounts are added
+    /// With `own_key` it is encrypted with a key of its own, which still has to be set up
+    /// with [`Crypto::init`](crate::crypto::Crypto::init)
+    pub fn create(&self, name: &str, own_key: bool) -> Result<Vault> {
+        validate_name(name)?;
+        let dir = self.dir(name);
+        if dir.join(METADATA_FILE).exists() {
+            return Err(AuthError::VaultExists(name.to_string()));
+        }
+
+        fs::create_dir_all(&dir).map_err(|e| {
+            AuthError::StorageFile(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+        let metadata = Metadata {
+            own_key,
+            key_file: None,
+        };
+        metadata.write(&dir)?;
+        Ok(self.vault(name, metadata))
+    }
+
+    /// Removes the directory of the named vault `name` with everything in it
+    /// Its key, if it has one, must be deleted beforehand
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.get(name)?;
+        let dir = self.dir(name);
+        fs::remove_dir_all(&dir).map_err(|e| {
+            AuthError::StorageFile(format!("Failed to delete {}: {}", dir.display(), e))
+        })
+    }
+
+    /// The default vault followed by the named vaults sorted by name
+    pub fn list(&self) -> Result<Vec<Vault>> {
+        let mut names = Vec::new();
+        match fs::read_dir(self.root.join(VAULTS_DIR)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    if entry.path().join(METADATA_FILE).exists() {
+                        names.push(entry.file_name().to_string_lossy().into_owned());
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        names.sort();
+
+        let mut vaults = vec![self.default_vault()];
+        for name in names {
+            vaults.push(self.get(&name)?);
+        }
+        Ok(vaults)
+    }
+
+    /// Picks the vault to use: the one at `path` if given, else the named vault
+    /// `name`, else the default vault
+    pub fn select(&self, path: Option<&Path>, name: Option<&str>) -> Result<Vault> {
+        match (path, name) {
+            (Some(path), _) => Ok(Vault::at_path(self, path.to_path_buf())),
+            (None, Some(name)) => self.get(name),
+            (None, None) => Ok(self.default_vault()),
+        }
+    }
+}
+
+/// Vault names become directory names and keyring entries, so only plain ASCII
+/// letters, digits, `-` and `_` are accepted
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(AuthError::InvalidParameter(format!(
+            "invalid vault name '{}', use letters, digits, '-' and '_'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Crypto, KeyMode};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn named_vaults_are_created_listed_and_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let vaults = Vaults::at(dir.path().to_path_buf()).unwrap();
+
+        let work = vaults.create("work", true).unwrap();
+        vaults.create("personal", false).unwrap();
+        assert!(matches!(
+            vaults.create("work", false),
+            Err(AuthError::VaultExists(_))
+        ));
+
+        let names: Vec<_> = vaults.list().unwrap().into_iter().map(|v| v.name).collect();
+        assert_eq!(
+            names,
+            [None, Some("personal".to_string()), Some("work".to_string())]
+        );
+
+        assert_eq!(vaults.get("work").unwrap(), work);
+        assert_ne!(work.key_slot, vaults.shared_key_slot());
+        assert_eq!(
+            vaults.get("personal").unwrap().key_slot,
+            vaults.shared_key_slot()
+        );
+        assert_ne!(work.path, vaults.default_vault().path);
+
+        vaults.delete("work").unwrap();
+        assert!(matches!(
+            vaults.get("work"),
+            Err(AuthError::VaultNotFound(_))
+        ));
+        assert_eq!(vaults.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_plain() {
+        let dir = tempfile::tempdir().unwrap();
+        let vaults = Vaults::at(dir.path().to_path_buf()).unwrap();
+
+        for name in ["", "../escape", "a/b", "work space", ".hidden"] {
+            assert!(
+                matches!(
+                    vaults.create(name, false),
+                    Err(AuthError::InvalidParameter(_))
+                ),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn path_takes_precedence_over_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let vaults = Vaults::at(dir.path().to_path_buf()).unwrap();
+        vaults.create("work", true).unwrap();
+
+        let path = dir.path().join("elsewhere.json");
+        let vault = vaults.select(Some(&path), Some("work")).unwrap();
+        assert_eq!(vault.path, path);
+        assert!(!vault.own_key);
+
+        assert_eq!(
+            vaults.select(None, Some("work")).unwrap().name.as_deref(),
+            Some("work")
+        );
+        assert_eq!(vaults.select(None, None).unwrap(), vaults.default_vault());
+    }
+
+    #[test]
+    fn key_file_is_recorded_in_the_vault_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("r-auth");
+        let vaults = Vaults::at(root.clone()).unwrap();
+        let key_file = dir.path().join("me.key");
+        let identity = age::x25519::Identity::generate();
+        fs::write(&key_file, identity.to_string().expose_secret()).unwrap();
+
+        let mode = KeyMode::KeyFile(key_file.clone());
+        let shared = vaults.shared_key_slot();
+        let path = Crypto::new(shared.clone()).init(mode.clone()).unwrap();
+        vaults.record_key_file(&shared, path.as_deref()).unwrap();
+        let work = vaults.create("work", true).unwrap();
+        let path = Crypto::new(work.key_slot.clone()).init(mode).unwrap();
+        vaults
+            .record_key_file(&work.key_slot, path.as_deref())
+            .unwrap();
+
+        let vaults = Vaults::at(root.clone()).unwrap();
+        let recorded = fs::canonicalize(&key_file).unwrap();
+        assert_eq!(
+            vaults.shared_key_slot().key_file(),
+            Some(recorded.as_path())
+        );
+        let work = vaults.get("work").unwrap();
+        assert!(work.own_key);
+        assert_eq!(work.key_slot.key_file(), Some(recorded.as_path()));
+
+        let crypto = Crypto::new(vaults.shared_key_slot());
+        assert!(matches!(
+            crypto.init(KeyMode::Keyring),
+            Err(AuthError::KeyExists)
+        ));
+        let encrypted = crypto.encrypt(b"secret").unwrap();
+        let decrypted = Crypto::from_identity(identity).decrypt(&encrypted).unwrap();
+        assert_eq!(decrypted, b"secret");
+
+        crypto.reset().unwrap();
+        vaults
+            .record_key_file(&vaults.shared_key_slot(), None)
+            .unwrap();
+        assert!(key_file.exists());
+        let vaults = Vaults::at(root).unwrap();
+        assert_eq!(vaults.shared_key_slot().key_file(), None);
+        assert!(vaults.get("work").unwrap().key_slot.key_file().is_some());
+    }
+}
@@ -0,0 +1,155 @@
+//! End-to-end use of a vault through the library, with the key kept in memory or in a
+//! passphrase-protected identity file so that no system keyring is needed
+
+use std::fs;
+use std::path::Path;
+
+use r_auth::account::{AccountParams, OtpKind};
+use r_auth::authenticator::TOTPAuthenticator;
+use r_auth::crypto::{Crypto, KeyMode, KeyStore, MemoryStore, PassphraseStore};
+use r_auth::error::AuthError;
+use r_auth::totp::TOTP;
+use secrecy::SecretString;
+
+/// RFC 4226 test secret, "12345678901234567890"
+const HOTP_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+fn hotp() -> AccountParams {
+    AccountParams {
+        kind: OtpKind::Hotp,
+        ..AccountParams::default()
+    }
+}
+
+/// Goes through init, add, code, remove and reset with the key in `store`
+fn run_lifecycle(path: &Path, store: impl KeyStore + Clone + 'static, mode: KeyMode) {
+    assert!(matches!(
+        TOTPAuthenticator::new(path, Crypto::from_key_store(store.clone())),
+        Err(AuthError::KeyNotFound)
+    ));
+
+    let crypto = Crypto::from_key_store(store.clone());
+    crypto.init(mode.clone()).unwrap();
+    assert!(matches!(crypto.init(mode), Err(AuthError::KeyExists)));
+
+    let mut vault = TOTPAuthenticator::new(path, crypto).unwrap();
+    let secret = vault
+        .add_account("GitHub", None, AccountParams::default(), false)
+        .unwrap();
+    vault
+        .add_account("VPN", Some(HOTP_SECRET), hotp(), false)
+        .unwrap();
+    assert!(!fs::read(path).unwrap().windows(6).any(|w| w == b"GitHub"));
+
+    let mut vault = TOTPAuthenticator::new(path, Crypto::from_key_store(store.clone())).unwrap();
+    assert_eq!(vault.list_accounts().len(), 2);
+
+    let code = vault.get_code("GitHub").unwrap().unwrap();
+    let now = TOTP::unix_time().unwrap();
+    let totp = TOTP::new(&secret).unwrap();
+    assert!(totp.verify(&code.code, now, 1).unwrap().is_some());
+    assert_eq!(code.period, Some(30));
+
+    assert_eq!(vault.get_code("VPN").unwrap().unwrap().code, "755224");
+    assert_eq!(vault.get_code("VPN").unwrap().unwrap().code, "287082");
+    assert!(vault.get_code("Missing").unwrap().is_none());
+
+    assert!(vault.remove_account("GitHub").unwrap());
+    assert!(!vault.remove_account("GitHub").unwrap());
+
+    let mut vault = TOTPAuthenticator::new(path, Crypto::from_key_store(store.clone())).unwrap();
+    assert_eq!(vault.list_accounts(), vec!["VPN".to_string()]);
+    assert_eq!(vault.get_code("VPN").unwrap().unwrap().code, "359152");
+
+    TOTPAuthenticator::reset(path).unwrap();
+    Crypto::from_key_store(store.clone()).reset().unwrap();
+    assert!(!path.exists());
+    assert!(!store.exists().unwrap());
+    assert!(matches!(
+        TOTPAuthenticator::new(path, Crypto::from_key_store(store)),
+        Err(AuthError::KeyNotFound)
+    ));
+}
+
+#[test]
+fn lifecycle_with_key_in_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    run_lifecycle(
+        &dir.path().join("accounts.json"),
+        MemoryStore::default(),
+        KeyMode::Keyring,
+    );
+}
+
+#[test]
+fn lifecycle_with_key_in_passphrase_protected_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = PassphraseStore::with_passphrase(
+        dir.path().join("identity.age"),
+        SecretString::from("correct horse"),
+    );
+    run_lifecycle(
+        &dir.path().join("accounts.json"),
+        store,
+        KeyMode::Passphrase,
+    );
+}
+
+#[test]
+fn identity_file_needs_its_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("accounts.json");
+    let identity_file = dir.path().join("identity.age");
+
+    let store =
+        PassphraseStore::with_passphrase(&identity_file, SecretString::from("correct horse"));
+    let crypto = Crypto::from_key_store(store);
+    crypto.init(KeyMode::Passphrase).unwrap();
+    let mut vault = TOTPAuthenticator::new(&path, crypto).unwrap();
+    vault
+        .add_account("VPN", Some(HOTP_SECRET), hotp(), false)
+        .unwrap();
+
+    let contents = fs::read(&identity_file).unwrap();
+    assert!(contents.starts_with(b"age-encryption.org"));
+    assert!(!contents.windows(16).any(|w| w == b"AGE-SECRET-KEY-1"));
+
+    let wrong =
+        PassphraseStore::with_passphrase(&identity_file, SecretString::from("battery staple"));
+    assert!(matches!(
+        TOTPAuthenticator::new(&path, Crypto::from_key_store(wrong)),
+        Err(AuthError::Passphrase(_))
+    ));
+
+    let right =
+        PassphraseStore::with_passphrase(&identity_file, SecretString::from("correct horse"));
+    let vault = TOTPAuthenticator::new(&path, Crypto::from_key_store(right)).unwrap();
+    assert_eq!(vault.list_accounts(), vec!["VPN".to_string()]);
+}
+
+#[cfg(unix)]
+#[test]
+fn passphrase_fd_is_read_once_and_left_open() {
+    use r_auth::crypto::PassphraseSource;
+    use secrecy::ExposeSecret;
+    use std::io::{Seek, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(b"correct horse\n").unwrap();
+    file.rewind().unwrap();
+
+    let source = PassphraseSource::Fd(file.as_raw_fd()).read_once().unwrap();
+    let PassphraseSource::Given(passphrase) = &source else {
+        panic!("expected the passphrase to be cached, got {:?}", source);
+    };
+    assert_eq!(passphrase.expose_secret(), "correct horse");
+
+    // A second read hits the end of the file rather than a closed descriptor
+    assert!(matches!(
+        PassphraseSource::Fd(file.as_raw_fd()).read_once(),
+        Err(AuthError::Passphrase(_))
+    ));
+    file.rewind().unwrap();
+    file.write_all(b"still open").unwrap();
+}